@@ -29,7 +29,7 @@ use crate::{
     config::{Config, Width},
     favorite::{read_favorite_devices_from_disk, save_favorite_devices_to_disk},
     notification::Notification,
-    profile::{AudioProfile, AudioDeviceId},
+    profile::{self, AudioDevice, AudioDeviceId, AudioProfile},
     requests::Requests,
     spinner::Spinner,
 };
@@ -74,6 +74,12 @@ pub struct App {
     pub profile_state: TableState,
     pub audio_device_id: Option<AudioDeviceId>,
     pub active_profile_index: Option<u32>,
+    /// Device the profile selector was opened for
+    pub audio_device: Option<AudioDevice>,
+    /// Profile index the user was warned about; a second press switches anyway
+    pub confirm_switch: Option<u32>,
+    /// The device of the profile selector is pinned to its profile
+    pub profile_locked: bool,
 }
 
 impl App {
@@ -140,6 +146,9 @@ impl App {
             profile_state: TableState::default(),
             audio_device_id: None,
             active_profile_index: None,
+            audio_device: None,
+            confirm_switch: None,
+            profile_locked: false,
         })
     }
 
@@ -288,7 +297,7 @@ impl App {
         let max_desc = self
             .available_profiles
             .iter()
-            .map(|p| p.description.len())
+            .map(|p| p.description.chars().count())
             .max()
             .unwrap_or(0);
         let popup_width = ((max_desc + 6) as u16).max(20).min(area.width - 4);
@@ -313,6 +322,9 @@ impl App {
 
         frame.render_widget(Clear, block);
 
+        // borders = 2, " ●" marker = 2
+        let max_label = block.width.saturating_sub(4) as usize;
+
         let rows: Vec<Row> = self
             .available_profiles
             .iter()
@@ -322,7 +334,8 @@ impl App {
                 } else {
                     ""
                 };
-                Row::new(vec![format!("{}{marker}", p.description)])
+                let label = profile::truncate_profile_label(&p.description, max_label);
+                Row::new(vec![format!("{label}{marker}")])
             })
             .collect();
 
@@ -338,7 +351,11 @@ impl App {
             )
             .block(
                 Block::default()
-                    .title(" Switch Profile ")
+                    .title(if self.profile_locked {
+                        " Switch Profile 󰌾 "
+                    } else {
+                        " Switch Profile "
+                    })
                     .title_style(Style::default().bold())
                     .borders(Borders::ALL)
                     .border_type(BorderType::Thick)
@@ -1,10 +1,28 @@
-use std::sync::{Arc, atomic::AtomicBool};
+use std::{
+    pin::Pin,
+    sync::{Arc, atomic::AtomicBool},
+    time::Duration,
+};
 
-use bluer::{Adapter, Address, Session, Uuid};
+use bluer::{
+    Adapter, AdapterEvent, AdapterProperty, Address, AddressType, DeviceEvent, DeviceProperty,
+    DiscoveryFilter, Session, Uuid,
+};
+use futures::{Stream, StreamExt, future, stream};
 
 use bluer::Device as BTDevice;
 
-use crate::app::AppResult;
+use anyhow::Context;
+
+use crate::{
+    app::AppResult,
+    profile::{
+        self, ProfileError,
+        bluez::{self, Track},
+    },
+    recent,
+    rfkill::{self, RfkillState},
+};
 
 #[derive(Debug, Clone)]
 pub struct Controller {
@@ -23,6 +41,10 @@ pub struct Controller {
 pub struct Device {
     device: BTDevice,
     pub addr: Address,
+    pub address_type: AddressType,
+    /// Every address this entry stands for; more than one for dual-mode devices
+    /// seen over both BR/EDR and LE.
+    pub addresses: Vec<Address>,
     pub icon: &'static str,
     pub alias: String,
     pub is_paired: bool,
@@ -67,7 +89,616 @@ impl Device {
     }
 }
 
+/// Connect `device`; traced when the `tracing` feature is enabled.
+///
+/// Dropping the future stops waiting but not BlueZ, which keeps trying to connect in
+/// the background; use [`cancel_connect`] to abort the attempt itself.
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(skip_all, fields(addr = %device.address()))
+)]
+pub async fn connect(device: &BTDevice) -> bluer::Result<()> {
+    Link::connect(device).await?;
+    let _ = recent::record_connection(&device.address());
+    Ok(())
+}
+
+/// Abort a pending connection attempt to the device, on whichever adapter knows it.
+/// Disconnects it if the attempt already went through.
+#[cfg_attr(feature = "tracing", tracing::instrument(fields(addr = %addr)))]
+pub async fn cancel_connect(addr: &Address) -> bluer::Result<()> {
+    let session = Session::new().await?;
+    let mut adapters = Vec::new();
+    for name in session.adapter_names().await? {
+        let addresses = session.adapter(&name)?.device_addresses().await?;
+        adapters.push((name, addresses));
+    }
+
+    let name = adapter_knowing(&adapters, addr)?;
+    cancel_link(&session.adapter(name)?.device(*addr)?).await
+}
+
+/// The device calls connecting and cancelling make, so tests can stand in for BlueZ.
+trait Link {
+    async fn connect(&self) -> bluer::Result<()>;
+    async fn disconnect(&self) -> bluer::Result<()>;
+    async fn is_connected(&self) -> bluer::Result<bool>;
+}
+
+impl Link for BTDevice {
+    async fn connect(&self) -> bluer::Result<()> {
+        BTDevice::connect(self).await
+    }
+
+    async fn disconnect(&self) -> bluer::Result<()> {
+        BTDevice::disconnect(self).await
+    }
+
+    async fn is_connected(&self) -> bluer::Result<bool> {
+        BTDevice::is_connected(self).await
+    }
+}
+
+/// Disconnect, which also makes BlueZ drop a pending `Connect`, and check that the
+/// device did not end up connected anyway.
+async fn cancel_link(link: &impl Link) -> bluer::Result<()> {
+    link.disconnect().await?;
+    if link.is_connected().await? {
+        return Err(bluer::Error {
+            kind: bluer::ErrorKind::Failed,
+            message: "The device is still connected".to_string(),
+        });
+    }
+    Ok(())
+}
+
+/// Name of the first adapter whose devices include `addr`.
+fn adapter_knowing<'a>(
+    adapters: &'a [(String, Vec<Address>)],
+    addr: &Address,
+) -> bluer::Result<&'a str> {
+    adapters
+        .iter()
+        .find(|(_, addresses)| addresses.contains(addr))
+        .map(|(name, _)| name.as_str())
+        .ok_or_else(|| bluer::Error {
+            kind: bluer::ErrorKind::DoesNotExist,
+            message: format!("No adapter knows {addr}"),
+        })
+}
+
+/// Whether the device registered an AVRCP media player, i.e. whether play/pause
+/// buttons would do anything.
+pub async fn has_media_controls(addr: &Address) -> bool {
+    let addr = *addr;
+    tokio::task::spawn_blocking(move || bluez::media_player(&addr).is_some())
+        .await
+        .unwrap_or_default()
+}
+
+/// Track the device's player reports. `None` without a player, and for players
+/// that offer controls but no metadata.
+pub async fn now_playing(addr: &Address) -> Option<Track> {
+    let addr = *addr;
+    tokio::task::spawn_blocking(move || bluez::media_player(&addr)?.track)
+        .await
+        .ok()
+        .flatten()
+}
+
+pub async fn play(addr: &Address) -> AppResult<()> {
+    media_player_command(addr, "Play").await
+}
+
+pub async fn pause(addr: &Address) -> AppResult<()> {
+    media_player_command(addr, "Pause").await
+}
+
+async fn media_player_command(addr: &Address, method: &'static str) -> AppResult<()> {
+    let addr = *addr;
+    tokio::task::spawn_blocking(move || {
+        let player =
+            bluez::media_player(&addr).with_context(|| format!("{addr} has no media player"))?;
+        bluez::media_player_command(&player.path, method)?;
+        Ok(())
+    })
+    .await?
+}
+
+/// Connect every paired audio device that was connected during the previous run of
+/// bluetui and is not connected right now, most recent first, and put each back on
+/// its preferred profile once its card shows up.
+///
+/// Devices the default adapter does not know are left out of the results.
+pub async fn reconnect_all_known() -> Vec<(Address, bluer::Result<()>)> {
+    let Ok(session) = Session::new().await else {
+        return Vec::new();
+    };
+    let Ok(adapter) = session.default_adapter().await else {
+        return Vec::new();
+    };
+
+    let mut known = Vec::new();
+    for addr in recent::last_session_devices() {
+        let Ok(device) = adapter.device(addr) else {
+            continue;
+        };
+        known.push(KnownDevice {
+            addr,
+            is_audio: device
+                .uuids()
+                .await
+                .ok()
+                .flatten()
+                .is_some_and(|uuids| AUDIO_UUIDS.iter().any(|u| uuids.contains(u))),
+            is_paired: device.is_paired().await.unwrap_or_default(),
+            is_connected: device.is_connected().await.unwrap_or_default(),
+        });
+    }
+
+    reconnect_each(&known, |addr| {
+        let adapter = &adapter;
+        async move {
+            let device = adapter.device(addr)?;
+            connect(&device).await?;
+            if let Some(audio_device) = profile::wait_for_card(&addr).await {
+                let _ = profile::apply_preferred_profile(&audio_device);
+            }
+            Ok(())
+        }
+    })
+    .await
+}
+
+/// What [`reconnect_all_known`] looks at to pick the devices to connect.
+struct KnownDevice {
+    addr: Address,
+    is_audio: bool,
+    is_paired: bool,
+    is_connected: bool,
+}
+
+/// Run `connect` on each paired audio device of `known` that is not connected, in
+/// order, and collect how each went.
+async fn reconnect_each<F, Fut>(
+    known: &[KnownDevice],
+    mut connect: F,
+) -> Vec<(Address, bluer::Result<()>)>
+where
+    F: FnMut(Address) -> Fut,
+    Fut: Future<Output = bluer::Result<()>>,
+{
+    let mut results = Vec::new();
+    for device in known {
+        if !device.is_audio || !device.is_paired || device.is_connected {
+            continue;
+        }
+        results.push((device.addr, connect(device.addr).await));
+    }
+    results
+}
+
+const POWER_WAIT_ATTEMPTS: u32 = 20;
+const POWER_WAIT_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Turn the default adapter on when it is off and off when it is on, and return
+/// the state it settled in once BlueZ reports it.
+pub async fn toggle_bluetooth() -> Result<bool, ProfileError> {
+    let session = Session::new().await?;
+    let adapter = session.default_adapter().await.map_err(|e| match e.kind {
+        bluer::ErrorKind::NotFound => ProfileError::NoAdapter,
+        _ => e.into(),
+    })?;
+
+    toggle_power(&adapter, rfkill::rfkill_state, POWER_WAIT_INTERVAL).await
+}
+
+/// The adapter calls [`toggle_bluetooth`] makes, so tests can stand in for BlueZ.
+trait PowerSwitch {
+    async fn is_powered(&self) -> bluer::Result<bool>;
+    async fn set_powered(&self, powered: bool) -> bluer::Result<()>;
+}
+
+impl PowerSwitch for Adapter {
+    async fn is_powered(&self) -> bluer::Result<bool> {
+        Adapter::is_powered(self).await
+    }
+
+    async fn set_powered(&self, powered: bool) -> bluer::Result<()> {
+        Adapter::set_powered(self, powered).await
+    }
+}
+
+/// Flip `adapter`, refusing to power it on while `rfkill_state` reports a block,
+/// then poll every `pause` until it reads back the new state.
+async fn toggle_power(
+    adapter: &impl PowerSwitch,
+    rfkill_state: impl FnOnce() -> RfkillState,
+    pause: Duration,
+) -> Result<bool, ProfileError> {
+    let powered = !adapter.is_powered().await?;
+    if powered {
+        let state = rfkill_state();
+        if state != RfkillState::Unblocked {
+            return Err(ProfileError::RadioBlocked(state));
+        }
+    }
+
+    adapter.set_powered(powered).await?;
+    for _ in 0..POWER_WAIT_ATTEMPTS {
+        if adapter.is_powered().await? == powered {
+            break;
+        }
+        tokio::time::sleep(pause).await;
+    }
+
+    Ok(adapter.is_powered().await?)
+}
+
+/// Service classes of audio devices: A2DP sink/source, headset, hands-free and the
+/// LE Audio published/streaming services.
+pub const AUDIO_UUIDS: [Uuid; 7] = [
+    Uuid::from_u128(0x0000110a_0000_1000_8000_00805f9b34fb),
+    Uuid::from_u128(0x0000110b_0000_1000_8000_00805f9b34fb),
+    Uuid::from_u128(0x00001108_0000_1000_8000_00805f9b34fb),
+    Uuid::from_u128(0x0000111e_0000_1000_8000_00805f9b34fb),
+    Uuid::from_u128(0x0000184e_0000_1000_8000_00805f9b34fb),
+    Uuid::from_u128(0x00001850_0000_1000_8000_00805f9b34fb),
+    Uuid::from_u128(0x00001853_0000_1000_8000_00805f9b34fb),
+];
+
+/// Roughly "same room"; weaker signals are left out.
+pub const DISCOVERY_RSSI_THRESHOLD: i16 = -75;
+
+/// Discovery filter that only reports nearby audio devices.
+pub fn audio_discovery_filter() -> DiscoveryFilter {
+    DiscoveryFilter {
+        uuids: AUDIO_UUIDS.into_iter().collect(),
+        rssi: Some(DISCOVERY_RSSI_THRESHOLD),
+        ..Default::default()
+    }
+}
+
+/// Restrict what the next scan on `adapter` reports. Has to be set before
+/// discovery starts.
+pub async fn set_discovery_filter(adapter: &Adapter, filter: DiscoveryFilter) -> bluer::Result<()> {
+    adapter.set_discovery_filter(filter).await
+}
+
+/// The filter the next scan on `adapter` will use, the default one when none was set.
+pub async fn discovery_filter(adapter: &Adapter) -> DiscoveryFilter {
+    adapter.discovery_filter().await
+}
+
+/// A scan on one adapter, stopped with [`DiscoverySession::stop`].
+///
+/// BlueZ keeps discovery running as long as any client asks for it, so stopping
+/// only withdraws this session: a scan that was already running, e.g. started
+/// from `bluetoothctl`, goes on.
+pub struct DiscoverySession {
+    adapter: Arc<Adapter>,
+    events: Pin<Box<dyn Stream<Item = AdapterEvent> + Send>>,
+    was_discovering: bool,
+}
+
+impl DiscoverySession {
+    pub async fn start(adapter: Arc<Adapter>) -> bluer::Result<Self> {
+        let was_discovering = adapter.is_discovering().await?;
+        let events = Box::pin(adapter.discover_devices().await?);
+
+        Ok(Self {
+            adapter,
+            events,
+            was_discovering,
+        })
+    }
+
+    /// Devices found since the scan started, each one once.
+    pub fn devices(&mut self) -> impl Stream<Item = Address> + '_ {
+        self.events.as_mut().filter_map(|event| {
+            future::ready(match event {
+                AdapterEvent::DeviceAdded(addr) => Some(addr),
+                _ => None,
+            })
+        })
+    }
+
+    /// Whether a scan was already running before this session started.
+    pub fn was_discovering(&self) -> bool {
+        self.was_discovering
+    }
+
+    /// Stop scanning and, unless someone else was already scanning, wait a moment
+    /// for the adapter to report discovery off again.
+    ///
+    /// Returns whether the adapter is back to its state before the session: `false`
+    /// when discovery is still on though nobody else was scanning at the start.
+    pub async fn stop(self) -> bluer::Result<bool> {
+        let Self {
+            adapter,
+            events,
+            was_discovering,
+        } = self;
+        // Dropping the last discovery stream is what makes bluer call StopDiscovery
+        drop(events);
+
+        let adapter = &*adapter;
+        restore_discovering(
+            was_discovering,
+            || adapter.is_discovering(),
+            Duration::from_millis(100),
+        )
+        .await
+    }
+}
+
+/// Wait for discovery to go off, polling `is_discovering` up to 20 times `pause`
+/// apart, unless it was already on before (`was_discovering`) and so stays on.
+/// Whether the adapter got back to that prior state.
+async fn restore_discovering<F>(
+    was_discovering: bool,
+    mut is_discovering: impl FnMut() -> F,
+    pause: Duration,
+) -> bluer::Result<bool>
+where
+    F: Future<Output = bluer::Result<bool>>,
+{
+    if was_discovering {
+        return Ok(true);
+    }
+    for _ in 0..20 {
+        if !is_discovering().await? {
+            return Ok(true);
+        }
+        tokio::time::sleep(pause).await;
+    }
+    Ok(false)
+}
+
+/// Whether the adapter is scanning, `false` when BlueZ can't be asked.
+pub async fn is_discovering(adapter: &Adapter) -> bool {
+    adapter.is_discovering().await.unwrap_or_default()
+}
+
+/// The adapter's discovering state: the current value first, then every change,
+/// whoever started or stopped the scan.
+pub async fn watch_discovering(adapter: &Adapter) -> impl Stream<Item = bool> + use<> {
+    let current = is_discovering(adapter).await;
+    match adapter.events().await {
+        Ok(events) => discovering_states(current, events).left_stream(),
+        Err(_) => discovering_states(current, stream::empty()).right_stream(),
+    }
+}
+
+/// `current`, then the discovering state carried by each of `events` that has one.
+fn discovering_states(
+    current: bool,
+    events: impl Stream<Item = AdapterEvent>,
+) -> impl Stream<Item = bool> {
+    let changes = events.filter_map(|event| future::ready(discovering_change(event)));
+    stream::once(future::ready(current)).chain(changes)
+}
+
+fn discovering_change(event: AdapterEvent) -> Option<bool> {
+    match event {
+        AdapterEvent::PropertyChanged(AdapterProperty::Discovering(discovering)) => {
+            Some(discovering)
+        }
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AdapterInfo {
+    /// Interface name, e.g. `hci0`
+    pub name: String,
+    pub address: Address,
+    pub is_powered: bool,
+}
+
+pub async fn list_adapters(session: &Session) -> bluer::Result<Vec<AdapterInfo>> {
+    let mut adapters = Vec::new();
+    for name in session.adapter_names().await? {
+        let adapter = session.adapter(&name)?;
+        adapters.push(AdapterInfo {
+            address: adapter.address().await?,
+            is_powered: adapter.is_powered().await?,
+            name,
+        });
+    }
+    Ok(adapters)
+}
+
+/// Connect the device through the adapter with address `adapter_addr` rather than
+/// whichever one BlueZ would pick.
+///
+/// Fails with `DoesNotExist` when no such adapter is present and `NotReady` when it
+/// is powered off.
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(fields(adapter = %adapter_addr, addr = %device_addr))
+)]
+pub async fn connect_via_adapter(
+    adapter_addr: &Address,
+    device_addr: &Address,
+) -> bluer::Result<()> {
+    let session = Session::new().await?;
+    let adapters = list_adapters(&session).await?;
+
+    let info = select_adapter(&adapters, adapter_addr)?;
+    let adapter = session.adapter(&info.name)?;
+    connect(&adapter.device(*device_addr)?).await
+}
+
+fn select_adapter<'a>(
+    adapters: &'a [AdapterInfo],
+    adapter_addr: &Address,
+) -> bluer::Result<&'a AdapterInfo> {
+    let info = adapters
+        .iter()
+        .find(|a| a.address == *adapter_addr)
+        .ok_or_else(|| bluer::Error {
+            kind: bluer::ErrorKind::DoesNotExist,
+            message: format!("No adapter with address {adapter_addr}"),
+        })?;
+
+    if !info.is_powered {
+        return Err(bluer::Error {
+            kind: bluer::ErrorKind::NotReady,
+            message: format!("Adapter {} ({adapter_addr}) is powered off", info.name),
+        });
+    }
+
+    Ok(info)
+}
+
+/// Battery percentage of the device: the current value first, then every change.
+///
+/// Ends when the device disconnects. Yields nothing when the device is unknown or
+/// does not report its battery.
+pub async fn watch_battery(addr: &Address) -> impl Stream<Item = u8> + use<> {
+    let watch = async {
+        let session = Session::new().await?;
+        let device = session.default_adapter().await?.device(*addr)?;
+        let current = device.battery_percentage().await?;
+        let events = device.events().await?;
+        Ok::<_, bluer::Error>((session, current, events))
+    };
+
+    let Ok((session, current, events)) = watch.await else {
+        return stream::empty().left_stream();
+    };
+
+    // The D-Bus connection has to outlive the stream
+    let changes = WithSession {
+        stream: battery_changes(events).boxed(),
+        _session: session,
+    };
+
+    stream::iter(current).chain(changes).right_stream()
+}
+
+/// Battery readings out of the device's events, until it disconnects.
+fn battery_changes(events: impl Stream<Item = DeviceEvent>) -> impl Stream<Item = u8> {
+    events
+        .take_while(|event| future::ready(!is_disconnect(event)))
+        .filter_map(|event| future::ready(battery_change(event)))
+}
+
+/// A stream of session events that keeps the session open while it is polled.
+struct WithSession<S> {
+    stream: S,
+    _session: Session,
+}
+
+impl<S: Stream + Unpin> Stream for WithSession<S> {
+    type Item = S::Item;
+
+    fn poll_next(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Option<Self::Item>> {
+        self.stream.poll_next_unpin(cx)
+    }
+}
+
+fn is_disconnect(event: &DeviceEvent) -> bool {
+    matches!(
+        event,
+        DeviceEvent::PropertyChanged(DeviceProperty::Connected(false))
+    )
+}
+
+fn battery_change(event: DeviceEvent) -> Option<u8> {
+    match event {
+        DeviceEvent::PropertyChanged(DeviceProperty::BatteryPercentage(percentage)) => {
+            Some(percentage)
+        }
+        _ => None,
+    }
+}
+
+/// A device as the main list shows it.
+#[derive(Debug, Clone)]
+pub struct DeviceEntry {
+    pub address: Address,
+    pub alias: String,
+    pub is_paired: bool,
+    pub is_trusted: bool,
+    pub is_connected: bool,
+    pub battery_percentage: Option<u8>,
+    /// The device's sink is the default output
+    pub is_active_output: bool,
+    /// What the audio backend knows about it, `None` while no card exists
+    pub audio: Option<profile::AudioDevice>,
+}
+
+/// Audio devices known to any adapter, connected ones first, then by name.
+///
+/// Devices that neither advertise an audio service nor have an audio card are left
+/// out. Errors while talking to BlueZ yield an empty or partial list.
+pub async fn list_devices_sorted() -> Vec<DeviceEntry> {
+    let Ok(session) = Session::new().await else {
+        return Vec::new();
+    };
+    let Ok(adapter_names) = session.adapter_names().await else {
+        return Vec::new();
+    };
+
+    let (mut audio_devices, default_output) = profile::audio_devices_with_default();
+    let mut entries = Vec::new();
+
+    for adapter in adapter_names.iter().filter_map(|n| session.adapter(n).ok()) {
+        let Ok(addresses) = adapter.device_addresses().await else {
+            continue;
+        };
+        for addr in addresses {
+            let Ok(device) = adapter.device(addr) else {
+                continue;
+            };
+
+            let audio = audio_devices
+                .iter()
+                .position(|d| d.address == addr)
+                .map(|i| audio_devices.swap_remove(i));
+            let advertises_audio = device
+                .uuids()
+                .await
+                .ok()
+                .flatten()
+                .is_some_and(|uuids| AUDIO_UUIDS.iter().any(|u| uuids.contains(u)));
+            if audio.is_none() && !advertises_audio {
+                continue;
+            }
+
+            entries.push(DeviceEntry {
+                address: addr,
+                alias: device.alias().await.unwrap_or_default(),
+                is_paired: device.is_paired().await.unwrap_or_default(),
+                is_trusted: device.is_trusted().await.unwrap_or_default(),
+                is_connected: device.is_connected().await.unwrap_or_default(),
+                battery_percentage: device.battery_percentage().await.ok().flatten(),
+                is_active_output: default_output == Some(addr),
+                audio,
+            });
+        }
+    }
+
+    sort_device_entries(&mut entries);
+    entries
+}
+
+fn sort_device_entries(entries: &mut [DeviceEntry]) {
+    entries.sort_by(|a, b| {
+        b.is_connected
+            .cmp(&a.is_connected)
+            .then_with(|| a.alias.to_lowercase().cmp(&b.alias.to_lowercase()))
+            .then_with(|| a.address.cmp(&b.address))
+    });
+}
+
 impl Controller {
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
     pub async fn get_all(
         session: Arc<Session>,
         favorite_devices: &[Address],
@@ -107,6 +738,10 @@ impl Controller {
         Ok(controllers)
     }
 
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(adapter = adapter.name()))
+    )]
     pub async fn get_all_devices(
         adapter: &Adapter,
         favorite_devices: &[Address],
@@ -120,6 +755,7 @@ impl Controller {
             let device = adapter.device(addr)?;
 
             let alias = device.alias().await?;
+            let address_type = device.address_type().await?;
             let icon = Device::get_icon(device.icon().await?.unwrap_or("-".to_string()).as_str());
             let is_paired = device.is_paired().await?;
             let is_trusted = device.is_trusted().await?;
@@ -136,6 +772,8 @@ impl Controller {
             let dev = Device {
                 device,
                 addr,
+                address_type,
+                addresses: vec![addr],
                 alias,
                 icon,
                 is_paired,
@@ -158,6 +796,7 @@ impl Controller {
             }
         }
 
+        let mut paired_devices = merge_dual_mode_devices(paired_devices).await;
         paired_devices.sort_by_key(|i| (!i.is_favorite, i.addr));
         new_devices.sort_by(|a, b| a.alias.cmp(&b.alias));
         devices_without_aliases.sort_by_key(|i| i.addr);
@@ -167,6 +806,112 @@ impl Controller {
     }
 }
 
+/// Dual-mode headsets can be listed twice under the same name, once per transport.
+/// Collapse those pairs into one entry.
+async fn merge_dual_mode_devices(devices: Vec<Device>) -> Vec<Device> {
+    let records: Vec<DualModeRecord> = devices.iter().map(DualModeRecord::from).collect();
+    let has_candidates = records
+        .iter()
+        .enumerate()
+        .any(|(i, a)| records[i + 1..].iter().any(|b| is_dual_mode_pair(a, b)));
+    if !has_candidates {
+        return devices;
+    }
+
+    // Listing the audio cards shells out to the sound server
+    let audio_addresses: Vec<Address> = tokio::task::spawn_blocking(profile::all_audio_devices)
+        .await
+        .unwrap_or_default()
+        .iter()
+        .map(|d| d.address)
+        .collect();
+
+    group_dual_mode_devices(devices, &audio_addresses)
+}
+
+/// Merge BR/EDR + LE entries sharing an alias, see [`dual_mode_groups`].
+fn group_dual_mode_devices(devices: Vec<Device>, audio_addresses: &[Address]) -> Vec<Device> {
+    let records: Vec<DualModeRecord> = devices.iter().map(DualModeRecord::from).collect();
+    let groups = dual_mode_groups(&records, audio_addresses);
+
+    let mut slots: Vec<Option<Device>> = devices.into_iter().map(Some).collect();
+    groups
+        .into_iter()
+        .filter_map(|group| {
+            let mut primary = slots[group[0]].take()?;
+            for other in group[1..].iter().filter_map(|&i| slots[i].take()) {
+                primary.addresses.extend(other.addresses);
+                primary.is_favorite |= other.is_favorite;
+                primary.battery_percentage =
+                    primary.battery_percentage.or(other.battery_percentage);
+            }
+            Some(primary)
+        })
+        .collect()
+}
+
+/// The parts of a [`Device`] that decide how dual-mode entries merge.
+#[derive(Debug, Clone, Copy)]
+struct DualModeRecord<'a> {
+    alias: &'a str,
+    addr: Address,
+    address_type: AddressType,
+    is_connected: bool,
+    /// Already stands for more than one address
+    merged: bool,
+}
+
+impl<'a> From<&'a Device> for DualModeRecord<'a> {
+    fn from(device: &'a Device) -> Self {
+        Self {
+            alias: &device.alias,
+            addr: device.addr,
+            address_type: device.address_type,
+            is_connected: device.is_connected,
+            merged: device.addresses.len() > 1,
+        }
+    }
+}
+
+/// Indices of the entries that collapse together, in order of first appearance. The
+/// primary comes first: the address backing an audio card, then a connected one,
+/// then the BR/EDR one.
+fn dual_mode_groups(records: &[DualModeRecord], audio_addresses: &[Address]) -> Vec<Vec<usize>> {
+    let rank = |r: &DualModeRecord| {
+        (
+            audio_addresses.contains(&r.addr),
+            r.is_connected,
+            r.address_type == AddressType::BrEdr,
+        )
+    };
+
+    let mut groups: Vec<Vec<usize>> = Vec::new();
+    for (i, record) in records.iter().enumerate() {
+        let Some(group) = groups
+            .iter_mut()
+            .find(|g| g.len() == 1 && is_dual_mode_pair(&records[g[0]], record))
+        else {
+            groups.push(vec![i]);
+            continue;
+        };
+
+        if rank(record) > rank(&records[group[0]]) {
+            group.insert(0, i);
+        } else {
+            group.push(i);
+        }
+    }
+
+    groups
+}
+
+fn is_dual_mode_pair(a: &DualModeRecord, b: &DualModeRecord) -> bool {
+    !a.merged
+        && !b.merged
+        && a.alias == b.alias
+        && (a.address_type == AddressType::BrEdr) != (b.address_type == AddressType::BrEdr)
+}
+
 fn is_mac_addr(s: &str) -> bool {
     if s.len() != 17 {
         return false;
@@ -186,3 +931,392 @@ fn is_mac_addr(s: &str) -> bool {
         && matches!(chars.next(), Some(c) if c.is_ascii_hexdigit())
         && chars.next().is_none()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn record<'a>(
+        alias: &'a str,
+        addr: &str,
+        address_type: AddressType,
+        is_connected: bool,
+    ) -> DualModeRecord<'a> {
+        DualModeRecord {
+            alias,
+            addr: Address::from_str(addr).unwrap(),
+            address_type,
+            is_connected,
+            merged: false,
+        }
+    }
+
+    #[test]
+    fn dual_mode_pair_becomes_one_entry() {
+        let records = [
+            record("Headset", "11:11:11:11:11:11", AddressType::LePublic, true),
+            record("Headset", "22:22:22:22:22:22", AddressType::BrEdr, false),
+            record("Mouse", "33:33:33:33:33:33", AddressType::LeRandom, true),
+        ];
+
+        // Connected wins over BR/EDR
+        assert_eq!(dual_mode_groups(&records, &[]), vec![vec![0, 1], vec![2]]);
+
+        // The address backing an audio card wins over everything
+        let audio = [records[1].addr];
+        assert_eq!(
+            dual_mode_groups(&records, &audio),
+            vec![vec![1, 0], vec![2]]
+        );
+    }
+
+    #[test]
+    fn same_transport_is_not_a_dual_mode_pair() {
+        let records = [
+            record("Headset", "11:11:11:11:11:11", AddressType::BrEdr, true),
+            record("Headset", "22:22:22:22:22:22", AddressType::BrEdr, false),
+        ];
+        assert_eq!(dual_mode_groups(&records, &[]), vec![vec![0], vec![1]]);
+    }
+
+    fn adapter(name: &str, addr: &str, is_powered: bool) -> AdapterInfo {
+        AdapterInfo {
+            name: name.into(),
+            address: Address::from_str(addr).unwrap(),
+            is_powered,
+        }
+    }
+
+    #[test]
+    fn select_adapter_by_address() {
+        let adapters = [
+            adapter("hci0", "00:1A:7D:DA:71:01", true),
+            adapter("hci1", "00:1A:7D:DA:71:02", false),
+        ];
+
+        let hci0 = Address::from_str("00:1A:7D:DA:71:01").unwrap();
+        assert_eq!(select_adapter(&adapters, &hci0).unwrap().name, "hci0");
+
+        let powered_off = Address::from_str("00:1A:7D:DA:71:02").unwrap();
+        let err = select_adapter(&adapters, &powered_off).unwrap_err();
+        assert!(matches!(err.kind, bluer::ErrorKind::NotReady));
+        assert!(err.message.contains("hci1"));
+
+        let missing = Address::from_str("00:1A:7D:DA:71:03").unwrap();
+        let err = select_adapter(&adapters, &missing).unwrap_err();
+        assert!(matches!(err.kind, bluer::ErrorKind::DoesNotExist));
+    }
+
+    #[test]
+    fn audio_discovery_filter_only_asks_for_nearby_audio() {
+        let filter = audio_discovery_filter();
+
+        assert_eq!(filter.rssi, Some(DISCOVERY_RSSI_THRESHOLD));
+        assert_eq!(filter.uuids.len(), AUDIO_UUIDS.len());
+        // A2DP sink and hands-free
+        assert!(
+            filter
+                .uuids
+                .contains(&Uuid::from_u128(0x0000110b_0000_1000_8000_00805f9b34fb))
+        );
+        assert!(
+            filter
+                .uuids
+                .contains(&Uuid::from_u128(0x0000111e_0000_1000_8000_00805f9b34fb))
+        );
+        // Everything else is left to BlueZ
+        assert_eq!(
+            DiscoveryFilter {
+                uuids: Default::default(),
+                rssi: None,
+                ..filter
+            },
+            DiscoveryFilter::default()
+        );
+    }
+
+    fn entry(alias: &str, addr: &str, is_connected: bool) -> DeviceEntry {
+        DeviceEntry {
+            address: Address::from_str(addr).unwrap(),
+            alias: alias.into(),
+            is_paired: true,
+            is_trusted: false,
+            is_connected,
+            battery_percentage: None,
+            is_active_output: false,
+            audio: None,
+        }
+    }
+
+    #[test]
+    fn connected_devices_sort_first_then_by_name() {
+        let mut entries = [
+            entry("speaker", "00:00:00:00:00:01", false),
+            entry("Buds", "00:00:00:00:00:02", true),
+            entry("Aux", "00:00:00:00:00:03", false),
+            entry("headset", "00:00:00:00:00:04", true),
+            entry("Speaker", "00:00:00:00:00:00", false),
+        ];
+        sort_device_entries(&mut entries);
+
+        let order: Vec<(&str, bool)> = entries
+            .iter()
+            .map(|e| (e.alias.as_str(), e.is_connected))
+            .collect();
+        assert_eq!(
+            order,
+            [
+                ("Buds", true),
+                ("headset", true),
+                ("Aux", false),
+                // Same name ignoring case, the address breaks the tie
+                ("Speaker", false),
+                ("speaker", false),
+            ]
+        );
+    }
+
+    #[test]
+    fn battery_changes_until_disconnect() {
+        let changed = |property| DeviceEvent::PropertyChanged(property);
+        let events = stream::iter([
+            changed(DeviceProperty::BatteryPercentage(80)),
+            changed(DeviceProperty::Connected(true)),
+            changed(DeviceProperty::Rssi(-60)),
+            changed(DeviceProperty::BatteryPercentage(75)),
+            changed(DeviceProperty::Connected(false)),
+            changed(DeviceProperty::BatteryPercentage(70)),
+        ]);
+
+        let readings: Vec<u8> = futures::executor::block_on(battery_changes(events).collect());
+        assert_eq!(readings, [80, 75]);
+
+        assert!(is_disconnect(&changed(DeviceProperty::Connected(false))));
+        assert!(!is_disconnect(&changed(DeviceProperty::Connected(true))));
+        assert_eq!(battery_change(changed(DeviceProperty::Trusted(true))), None);
+    }
+
+    #[test]
+    fn cancel_connect_goes_through_the_adapter_that_knows_the_device() {
+        let headset = Address::from_str("AA:BB:CC:DD:EE:FF").unwrap();
+        let mouse = Address::from_str("11:22:33:44:55:66").unwrap();
+        let adapters = [
+            ("hci0".to_string(), vec![mouse]),
+            ("hci1".to_string(), vec![mouse, headset]),
+        ];
+
+        assert_eq!(adapter_knowing(&adapters, &headset).unwrap(), "hci1");
+        assert_eq!(adapter_knowing(&adapters, &mouse).unwrap(), "hci0");
+
+        let unknown = Address::from_str("00:00:00:00:00:01").unwrap();
+        let err = adapter_knowing(&adapters, &unknown).unwrap_err();
+        assert!(matches!(err.kind, bluer::ErrorKind::DoesNotExist));
+    }
+
+    #[tokio::test]
+    async fn stopping_discovery_restores_the_prior_state() {
+        let pause = Duration::from_millis(1);
+
+        // Someone else was scanning: leave it on and don't even ask
+        let restored = restore_discovering(true, || async { unreachable!() }, pause).await;
+        assert!(restored.unwrap());
+
+        // BlueZ takes a few polls to report discovery off
+        let mut polls = 0;
+        let restored = restore_discovering(
+            false,
+            || {
+                polls += 1;
+                future::ready(Ok(polls < 3))
+            },
+            pause,
+        )
+        .await;
+        assert!(restored.unwrap());
+        assert_eq!(polls, 3);
+
+        // Still on after every poll
+        let restored = restore_discovering(false, || future::ready(Ok(true)), pause).await;
+        assert!(!restored.unwrap());
+
+        let failed = restore_discovering(
+            false,
+            || {
+                future::ready(Err(bluer::Error {
+                    kind: bluer::ErrorKind::NotReady,
+                    message: String::new(),
+                }))
+            },
+            pause,
+        )
+        .await;
+        assert!(failed.is_err());
+    }
+
+    #[test]
+    fn reconnect_skips_connected_and_non_audio_devices() {
+        let known = |last: u8, is_audio, is_paired, is_connected| KnownDevice {
+            addr: Address::new([0xAA, 0xBB, 0xCC, 0xDD, 0xEE, last]),
+            is_audio,
+            is_paired,
+            is_connected,
+        };
+        let devices = [
+            known(1, true, true, false),
+            known(2, true, true, true),
+            known(3, false, true, false),
+            known(4, true, false, false),
+            known(5, true, true, false),
+        ];
+
+        let mut attempted = Vec::new();
+        let results = futures::executor::block_on(reconnect_each(&devices, |addr| {
+            attempted.push(addr);
+            future::ready(match addr.0[5] {
+                5 => Err(bluer::Error {
+                    kind: bluer::ErrorKind::Failed,
+                    message: "Page Timeout".into(),
+                }),
+                _ => Ok(()),
+            })
+        }));
+
+        assert_eq!(attempted, [devices[0].addr, devices[4].addr]);
+        assert_eq!(results.len(), 2);
+        assert!(results[0].1.is_ok());
+        assert_eq!(results[1].0, devices[4].addr);
+        assert!(results[1].1.is_err());
+    }
+
+    #[test]
+    fn discovering_stream_starts_with_the_current_state() {
+        let addr = Address::new([0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0xFF]);
+        let events = stream::iter([
+            AdapterEvent::PropertyChanged(AdapterProperty::Discovering(true)),
+            AdapterEvent::DeviceAdded(addr),
+            AdapterEvent::PropertyChanged(AdapterProperty::Powered(true)),
+            AdapterEvent::PropertyChanged(AdapterProperty::Discovering(false)),
+        ]);
+
+        let states: Vec<bool> =
+            futures::executor::block_on(discovering_states(false, events).collect());
+        assert_eq!(states, [false, true, false]);
+
+        let states: Vec<bool> =
+            futures::executor::block_on(discovering_states(true, stream::empty()).collect());
+        assert_eq!(states, [true]);
+    }
+
+    /// An adapter that takes `lag` reads to report a new power state.
+    struct SlowAdapter {
+        powered: std::cell::Cell<bool>,
+        target: std::cell::Cell<Option<bool>>,
+        lag: std::cell::Cell<u32>,
+        reads: std::cell::Cell<u32>,
+    }
+
+    impl SlowAdapter {
+        fn new(powered: bool, lag: u32) -> Self {
+            Self {
+                powered: powered.into(),
+                target: None.into(),
+                lag: lag.into(),
+                reads: 0.into(),
+            }
+        }
+    }
+
+    impl PowerSwitch for SlowAdapter {
+        async fn is_powered(&self) -> bluer::Result<bool> {
+            self.reads.set(self.reads.get() + 1);
+            if let Some(target) = self.target.get() {
+                match self.lag.get() {
+                    0 => self.powered.set(target),
+                    lag => self.lag.set(lag - 1),
+                }
+            }
+            Ok(self.powered.get())
+        }
+
+        async fn set_powered(&self, powered: bool) -> bluer::Result<()> {
+            self.target.set(Some(powered));
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn toggle_waits_to_read_back_the_new_state() {
+        let pause = Duration::from_millis(1);
+
+        let adapter = SlowAdapter::new(false, 3);
+        let unblocked = || RfkillState::Unblocked;
+        assert_eq!(toggle_power(&adapter, unblocked, pause).await, Ok(true));
+        assert!(adapter.powered.get());
+        // One read to decide, the lagging ones, the settled one and the final one
+        assert_eq!(adapter.reads.get(), 6);
+
+        let adapter = SlowAdapter::new(true, 0);
+        let blocked = || RfkillState::SoftBlocked;
+        assert_eq!(toggle_power(&adapter, blocked, pause).await, Ok(false));
+
+        let adapter = SlowAdapter::new(false, 0);
+        assert_eq!(
+            toggle_power(&adapter, blocked, pause).await,
+            Err(ProfileError::RadioBlocked(RfkillState::SoftBlocked))
+        );
+        assert_eq!(adapter.target.get(), None);
+
+        // Never settles: report what BlueZ says, not what was asked
+        let adapter = SlowAdapter::new(false, u32::MAX);
+        assert_eq!(toggle_power(&adapter, unblocked, pause).await, Ok(false));
+        assert_eq!(adapter.reads.get(), POWER_WAIT_ATTEMPTS + 2);
+    }
+
+    /// A device whose connection attempt hangs until it is cancelled, like an
+    /// out-of-range headset paging.
+    #[derive(Default)]
+    struct PagingDevice {
+        connected: std::cell::Cell<bool>,
+        cancelled: tokio::sync::Notify,
+    }
+
+    impl Link for PagingDevice {
+        async fn connect(&self) -> bluer::Result<()> {
+            self.cancelled.notified().await;
+            Err(bluer::Error {
+                kind: bluer::ErrorKind::Failed,
+                message: "Operation already in progress".into(),
+            })
+        }
+
+        async fn disconnect(&self) -> bluer::Result<()> {
+            self.connected.set(false);
+            self.cancelled.notify_one();
+            Ok(())
+        }
+
+        async fn is_connected(&self) -> bluer::Result<bool> {
+            Ok(self.connected.get())
+        }
+    }
+
+    #[tokio::test]
+    async fn cancelling_a_connect_leaves_the_device_disconnected() {
+        let device = PagingDevice::default();
+        let (connected, cancelled) = tokio::join!(device.connect(), async {
+            tokio::task::yield_now().await;
+            cancel_link(&device).await
+        });
+
+        assert!(connected.is_err());
+        assert!(cancelled.is_ok());
+        assert_eq!(device.is_connected().await, Ok(false));
+
+        // The attempt went through before the cancel: disconnect it
+        let device = PagingDevice::default();
+        device.connected.set(true);
+        assert!(cancel_link(&device).await.is_ok());
+        assert!(!device.connected.get());
+    }
+}
@@ -1,5 +1,7 @@
 use std::path::PathBuf;
 
+use bluer::Address;
+
 use clap::{Command, arg, crate_description, crate_name, crate_version, value_parser};
 
 pub fn cli() -> Command {
@@ -14,4 +16,25 @@ pub fn cli() -> Command {
                 .help("Config file path")
                 .value_parser(value_parser!(PathBuf)),
         )
+        .arg(
+            arg!(--"event-log" <path>)
+                .id("event-log")
+                .required(false)
+                .help("Append profile, codec and volume changes to this file as JSON lines")
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .arg(
+            arg!(--"mock-audio" <path>)
+                .id("mock-audio")
+                .required(false)
+                .help("Serve fake audio devices from this JSON file instead of PipeWire/PulseAudio")
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .arg(
+            arg!(--diagnostics[address])
+                .id("diagnostics")
+                .num_args(0..=1)
+                .help("Print a diagnostics report to paste in bug reports, for the device if given")
+                .value_parser(value_parser!(Address)),
+        )
 }
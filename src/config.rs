@@ -24,6 +24,20 @@ pub struct Config {
     #[serde(default = "default_esc_quit")]
     pub esc_quit: bool,
 
+    /// Send volume changes to the headset over AVRCP when it supports absolute volume
+    #[serde(default)]
+    pub prefer_hw_volume: bool,
+
+    /// Only list nearby devices advertising an audio service while scanning
+    #[serde(default)]
+    pub audio_only_scan: bool,
+
+    /// Codecs to switch to after connecting from the TUI, most wanted first. Empty
+    /// keeps whatever the backend negotiated; `profile::connect_preferred_codec`
+    /// itself ranks by quality then.
+    #[serde(default, deserialize_with = "deserialize_codec_preference")]
+    pub codec_preference: Vec<String>,
+
     #[serde(default)]
     pub adapter: Adapter,
 
@@ -128,6 +142,12 @@ pub struct PairedDevice {
 
     #[serde(default = "default_switch_profile")]
     pub switch_profile: char,
+
+    #[serde(default = "default_volume_up")]
+    pub volume_up: char,
+
+    #[serde(default = "default_volume_down")]
+    pub volume_down: char,
 }
 
 impl Default for PairedDevice {
@@ -138,6 +158,8 @@ impl Default for PairedDevice {
             toggle_favorite: 'f',
             rename: 'e',
             switch_profile: 'a',
+            volume_up: '+',
+            volume_down: '-',
         }
     }
 }
@@ -165,6 +187,29 @@ where
     }
 }
 
+fn deserialize_codec_preference<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let codecs = Vec::<String>::deserialize(deserializer)?;
+
+    codecs
+        .into_iter()
+        .map(|codec| {
+            let codec = codec.to_lowercase();
+            if crate::profile::is_known_codec(&codec) {
+                Ok(codec)
+            } else {
+                eprintln!("Wrong config: unknown codec {}", codec);
+                eprintln!(
+                    "The possible values are: ldac, aptx_hd, lc3, aptx, aac, sbc_xq, aptx_ll, faststream, sbc, msbc, cvsd"
+                );
+                std::process::exit(1);
+            }
+        })
+        .collect()
+}
+
 fn default_layout() -> Flex {
     Flex::SpaceAround
 }
@@ -209,6 +254,14 @@ fn default_switch_profile() -> char {
     'a'
 }
 
+fn default_volume_up() -> char {
+    '+'
+}
+
+fn default_volume_down() -> char {
+    '-'
+}
+
 impl Config {
     pub fn new(config_file_path: Option<PathBuf>) -> Self {
         let conf_path = config_file_path.unwrap_or(
@@ -0,0 +1,527 @@
+use std::fmt;
+
+use bluer::Address;
+
+use crate::{
+    profile::{self, AudioDevice, Backend, BackendVersions, HwVolumeSupport, bluez},
+    rfkill::{self, RfkillState},
+};
+
+/// Everything worth knowing when "nothing works": radio block state, the audio
+/// backend in use and, when an address is given, what that backend knows about
+/// the device.
+#[derive(Debug, Clone)]
+pub struct Diagnosis {
+    pub rfkill: RfkillState,
+    pub backend: Option<Backend>,
+    pub versions: BackendVersions,
+    pub address: Option<Address>,
+    pub device: Option<AudioDevice>,
+    pub hw_volume: Option<HwVolumeSupport>,
+    /// PipeWire suspended the device's nodes
+    pub suspended: Option<bool>,
+    /// A2DP codecs and whether the PipeWire config enables them
+    pub codec_config: Vec<(String, bool)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReadinessCheck {
+    pub name: &'static str,
+    pub passed: bool,
+}
+
+/// Checks from the radio up to the audio profile, in the order they depend on each
+/// other: the first failing one is usually what to fix.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReadinessReport {
+    pub checks: Vec<ReadinessCheck>,
+    pub ready: bool,
+}
+
+impl ReadinessReport {
+    fn new(checks: Vec<ReadinessCheck>) -> Self {
+        let ready = checks.iter().all(|c| c.passed);
+        Self { checks, ready }
+    }
+
+    fn from_inputs(inputs: &ReadinessInputs) -> Self {
+        let check = |name, passed| ReadinessCheck { name, passed };
+        let device = inputs.device.as_ref();
+
+        Self::new(vec![
+            check("bluetooth not blocked", inputs.unblocked),
+            check("adapter powered", inputs.adapter_powered),
+            check("device connected", inputs.connected),
+            check("audio backend present", inputs.backend_present),
+            check("bluetooth audio support loaded", inputs.bluez5_loaded),
+            check("audio device enumerated", device.is_some()),
+            check(
+                "profiles available",
+                device.is_some_and(|d| !d.pending && !d.profiles.is_empty()),
+            ),
+        ])
+    }
+
+    pub fn first_failure(&self) -> Option<&ReadinessCheck> {
+        self.checks.iter().find(|c| !c.passed)
+    }
+}
+
+impl fmt::Display for ReadinessReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for check in &self.checks {
+            let mark = if check.passed { "ok" } else { "FAIL" };
+            writeln!(f, "[{mark:>4}] {}", check.name)?;
+        }
+        writeln!(f, "ready: {}", if self.ready { "yes" } else { "no" })
+    }
+}
+
+/// Whether the device is ready to play audio, check by check.
+pub fn readiness(addr: &Address) -> ReadinessReport {
+    ReadinessReport::from_inputs(&ReadinessInputs {
+        unblocked: rfkill::rfkill_state() == RfkillState::Unblocked,
+        adapter_powered: bluez::adapter_powered().unwrap_or_default(),
+        connected: bluez::info(addr).is_some_and(|i| i.connected),
+        backend_present: profile::detect_backend().is_some(),
+        bluez5_loaded: profile::bluez5_loaded(),
+        device: profile::get_audio_device(addr),
+    })
+}
+
+/// What [`readiness`] probes, one field per check.
+#[derive(Debug, Clone)]
+struct ReadinessInputs {
+    unblocked: bool,
+    adapter_powered: bool,
+    connected: bool,
+    backend_present: bool,
+    bluez5_loaded: bool,
+    device: Option<AudioDevice>,
+}
+
+pub fn diagnose(addr: Option<&Address>) -> Diagnosis {
+    Diagnosis {
+        rfkill: rfkill::rfkill_state(),
+        backend: profile::detect_backend(),
+        versions: profile::backend_versions(),
+        address: addr.copied(),
+        device: addr.and_then(profile::get_audio_device),
+        hw_volume: addr.map(profile::hw_volume_support),
+        suspended: addr.and_then(profile::is_node_suspended),
+        codec_config: profile::codec_config_state(),
+    }
+}
+
+impl fmt::Display for Diagnosis {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "rfkill: {}", self.rfkill)?;
+        if let Some(hint) = self.rfkill.hint() {
+            write!(f, " ({hint})")?;
+        }
+        writeln!(f)?;
+
+        match self.backend {
+            Some(Backend::Pipewire) => writeln!(f, "backend: pipewire")?,
+            Some(Backend::Pulseaudio) => writeln!(f, "backend: pulseaudio")?,
+            Some(Backend::Mock) => writeln!(f, "backend: mock")?,
+            None => writeln!(f, "backend: none")?,
+        }
+        writeln!(
+            f,
+            "pipewire: {}",
+            self.versions.pipewire.as_deref().unwrap_or("not installed")
+        )?;
+        writeln!(
+            f,
+            "pulseaudio: {}",
+            self.versions
+                .pulseaudio
+                .as_deref()
+                .unwrap_or("not installed")
+        )?;
+
+        let disabled: Vec<&str> = self
+            .codec_config
+            .iter()
+            .filter(|(_, enabled)| !enabled)
+            .map(|(codec, _)| codec.as_str())
+            .collect();
+        if self.backend == Some(Backend::Pipewire) && !disabled.is_empty() {
+            writeln!(f, "codecs disabled in config: {}", disabled.join(", "))?;
+        }
+
+        let Some(addr) = self.address else {
+            return Ok(());
+        };
+        match &self.device {
+            Some(device) => {
+                writeln!(f, "device {addr}: {:?}", device.id)?;
+                let active = device.active_profile();
+                match active {
+                    Some(p) => writeln!(f, "  profile: {} ({})", p.name, p.kind().label())?,
+                    None => writeln!(f, "  profile: none")?,
+                }
+                writeln!(
+                    f,
+                    "  codec: {}",
+                    device.codec.as_deref().unwrap_or("unknown")
+                )?;
+                writeln!(f, "  profiles: {}", device.profiles.len())?;
+                if self.suspended == Some(true) {
+                    writeln!(f, "  state: idle (will wake on playback)")?;
+                }
+                if let Some(hw_volume) = self.hw_volume {
+                    let support = |s: Option<bool>| match s {
+                        Some(true) => "yes",
+                        Some(false) => "no",
+                        None => "-",
+                    };
+                    writeln!(
+                        f,
+                        "  hw volume: pipewire {}, pulseaudio {}",
+                        support(hw_volume.pipewire),
+                        support(hw_volume.pulseaudio)
+                    )?;
+                }
+            }
+            None => writeln!(f, "device {addr}: no audio card")?,
+        }
+        Ok(())
+    }
+}
+
+/// How much of each Bluetooth address a [`diagnostics_bundle_with`] leaves readable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Redaction {
+    /// Keep addresses as they are
+    Off,
+    /// Hide the manufacturer part (OUI), the first three bytes
+    #[default]
+    MaskOui,
+    /// Hide whole addresses
+    MaskAll,
+}
+
+impl Redaction {
+    /// Mask every MAC address in `text`, whichever device or adapter it belongs to,
+    /// in each spelling the backends use (`AA:BB:..`, `AA_BB_..`, either case).
+    fn apply(&self, text: &str) -> String {
+        let masked = |i: usize| match self {
+            Redaction::Off => false,
+            Redaction::MaskOui => i < 3,
+            Redaction::MaskAll => true,
+        };
+        if *self == Redaction::Off {
+            return text.to_string();
+        }
+
+        let mut redacted = String::with_capacity(text.len());
+        let mut rest = text;
+        while let Some(c) = rest.chars().next() {
+            let Some(separator) = mac_at(text, text.len() - rest.len()) else {
+                redacted.push(c);
+                rest = &rest[c.len_utf8()..];
+                continue;
+            };
+
+            for i in 0..6 {
+                if i > 0 {
+                    redacted.push(separator);
+                }
+                redacted.push_str(if masked(i) {
+                    "XX"
+                } else {
+                    &rest[i * 3..i * 3 + 2]
+                });
+            }
+            rest = &rest[17..];
+        }
+        redacted
+    }
+}
+
+/// The separator of the MAC address starting at byte `at` of `text`, if one does:
+/// six hex pairs joined by `:` or `_`, not part of a longer hex run.
+fn mac_at(text: &str, at: usize) -> Option<char> {
+    let bytes = text.as_bytes();
+    let mac = bytes.get(at..at + 17)?;
+    let separator = mac[2];
+    if separator != b':' && separator != b'_' {
+        return None;
+    }
+
+    let well_formed = mac.iter().enumerate().all(|(i, &b)| match i % 3 {
+        2 => b == separator,
+        _ => b.is_ascii_hexdigit(),
+    });
+    // A separator right next to it only extends it when more hex follows
+    let hex = |i: Option<usize>| {
+        i.and_then(|i| bytes.get(i))
+            .is_some_and(u8::is_ascii_hexdigit)
+    };
+    let sep = |i: Option<usize>| i.and_then(|i| bytes.get(i)) == Some(&separator);
+    let before = at.checked_sub(1);
+    let after = Some(at + 17);
+    let joined = hex(before)
+        || (sep(before) && hex(at.checked_sub(2)))
+        || hex(after)
+        || (sep(after) && hex(Some(at + 18)));
+
+    (well_formed && !joined).then_some(separator as char)
+}
+
+/// Everything a bug report needs in one paste, with the manufacturer part of every
+/// Bluetooth address masked.
+pub fn diagnostics_bundle(addr: Option<&Address>) -> String {
+    diagnostics_bundle_with(addr, Redaction::default())
+}
+
+pub fn diagnostics_bundle_with(addr: Option<&Address>, redaction: Redaction) -> String {
+    BundleInputs {
+        diagnosis: diagnose(addr),
+        bluez5_loaded: profile::bluez5_loaded(),
+        adapter_powered: bluez::adapter_powered(),
+        device: addr.map(|addr| BundleDevice {
+            readiness: readiness(addr),
+            explain_match: profile::explain_match(addr),
+            backend_json: profile::raw_backend_json(addr),
+        }),
+    }
+    .render(redaction)
+}
+
+/// What [`diagnostics_bundle_with`] writes out, one field per section.
+struct BundleInputs {
+    diagnosis: Diagnosis,
+    bluez5_loaded: bool,
+    adapter_powered: Option<bool>,
+    /// Only with an address
+    device: Option<BundleDevice>,
+}
+
+struct BundleDevice {
+    readiness: ReadinessReport,
+    /// Which backend objects matched the address and why, see [`profile::explain_match`]
+    explain_match: Option<String>,
+    backend_json: Option<String>,
+}
+
+impl BundleInputs {
+    fn render(&self, redaction: Redaction) -> String {
+        let mut sections = vec![
+            format!("## {} {}", clap::crate_name!(), clap::crate_version!()),
+            format!("## diagnosis\n{}", self.diagnosis.to_string().trim_end()),
+            format!(
+                "## system\nbluetooth audio support loaded: {}\nadapter powered: {}",
+                if self.bluez5_loaded { "yes" } else { "no" },
+                match self.adapter_powered {
+                    Some(true) => "yes",
+                    Some(false) => "no",
+                    None => "unknown",
+                }
+            ),
+        ];
+
+        if let Some(device) = &self.device {
+            sections.push(format!(
+                "## readiness\n{}",
+                device.readiness.to_string().trim_end()
+            ));
+            sections.push(format!(
+                "## backend match\n{}",
+                device.explain_match.as_deref().unwrap_or("no backend")
+            ));
+            sections.push(format!(
+                "## backend objects\n{}",
+                device.backend_json.as_deref().unwrap_or("none")
+            ));
+        }
+
+        redaction.apply(&sections.join("\n\n"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::profile::{AudioDeviceId, AudioProfile, ProfileCategory};
+    use std::str::FromStr;
+
+    fn inputs() -> ReadinessInputs {
+        ReadinessInputs {
+            unblocked: true,
+            adapter_powered: true,
+            connected: true,
+            backend_present: true,
+            bluez5_loaded: true,
+            device: Some(AudioDevice {
+                id: AudioDeviceId::Pipewire(40),
+                address: Address::from_str("AA:BB:CC:DD:EE:FF").unwrap(),
+                name: None,
+                profiles: vec![AudioProfile {
+                    index: 1,
+                    name: "a2dp-sink".into(),
+                    description: "High Fidelity Playback (A2DP Sink)".into(),
+                    available: true,
+                    category: ProfileCategory::Output,
+                }],
+                active_profile_index: Some(1),
+                codec: None,
+                volume: None,
+                format: None,
+                pending: false,
+            }),
+        }
+    }
+
+    fn first_failure(inputs: &ReadinessInputs) -> Option<&'static str> {
+        ReadinessReport::from_inputs(inputs)
+            .first_failure()
+            .map(|c| c.name)
+    }
+
+    #[test]
+    fn ready_when_every_check_passes() {
+        let report = ReadinessReport::from_inputs(&inputs());
+        assert!(report.ready);
+        assert_eq!(report.checks.len(), 7);
+        assert!(report.to_string().ends_with("ready: yes\n"));
+    }
+
+    #[test]
+    fn first_failure_names_what_to_fix() {
+        type Break = fn(&mut ReadinessInputs);
+        let failing: [(Break, &str); 6] = [
+            (|i| i.unblocked = false, "bluetooth not blocked"),
+            (|i| i.adapter_powered = false, "adapter powered"),
+            (|i| i.connected = false, "device connected"),
+            (|i| i.backend_present = false, "audio backend present"),
+            (
+                |i| i.bluez5_loaded = false,
+                "bluetooth audio support loaded",
+            ),
+            (|i| i.device = None, "audio device enumerated"),
+        ];
+        for (break_it, expected) in failing {
+            let mut inputs = inputs();
+            break_it(&mut inputs);
+            assert_eq!(first_failure(&inputs), Some(expected));
+        }
+
+        let mut pending = inputs();
+        if let Some(device) = &mut pending.device {
+            device.pending = true;
+        }
+        assert_eq!(first_failure(&pending), Some("profiles available"));
+
+        // Checks after the first failure are still reported
+        let mut several = inputs();
+        several.adapter_powered = false;
+        several.device = None;
+        let report = ReadinessReport::from_inputs(&several);
+        assert!(!report.ready);
+        assert_eq!(report.checks.iter().filter(|c| !c.passed).count(), 3);
+    }
+
+    fn bundle() -> BundleInputs {
+        let inputs = inputs();
+        let device = inputs.device.clone();
+        BundleInputs {
+            diagnosis: Diagnosis {
+                rfkill: RfkillState::Unblocked,
+                backend: Some(Backend::Pipewire),
+                versions: BackendVersions {
+                    pipewire: Some("1.2.7".into()),
+                    pulseaudio: None,
+                },
+                address: device.as_ref().map(|d| d.address),
+                device,
+                hw_volume: Some(HwVolumeSupport::default()),
+                suspended: Some(false),
+                codec_config: vec![("ldac".into(), false)],
+            },
+            bluez5_loaded: true,
+            adapter_powered: Some(true),
+            device: Some(BundleDevice {
+                readiness: ReadinessReport::from_inputs(&inputs),
+                explain_match: Some(
+                    "pw-dump object 40 (PipeWire:Interface:Device): api.bluez5.address = AA:BB:CC:DD:EE:FF"
+                        .into(),
+                ),
+                backend_json: Some(
+                    r#"[{"device.name": "bluez_card.AA_BB_CC_DD_EE_FF", "api.bluez5.address": "aa:bb:cc:dd:ee:ff", "api.bluez5.adapter": "11:22:33:44:55:66"}]"#
+                        .into(),
+                ),
+            }),
+        }
+    }
+
+    #[test]
+    fn bundle_has_every_section() {
+        let text = bundle().render(Redaction::Off);
+        for section in [
+            "## bluetui ",
+            "## diagnosis\nrfkill: unblocked",
+            "## system\nbluetooth audio support loaded: yes\nadapter powered: yes",
+            "## readiness\n",
+            "## backend match\npw-dump object 40 (PipeWire:Interface:Device): api.bluez5.address = ",
+            "## backend objects\n[{",
+        ] {
+            assert!(text.contains(section), "{section} missing from\n{text}");
+        }
+        assert!(text.contains("pipewire: 1.2.7"));
+        assert!(text.contains("ready: yes"));
+        assert!(text.contains("AA:BB:CC:DD:EE:FF"));
+
+        let mut no_device = bundle();
+        no_device.device = None;
+        no_device.diagnosis.address = None;
+        let text = no_device.render(Redaction::MaskAll);
+        assert!(!text.contains("## readiness"));
+        assert!(!text.contains("## backend match"));
+        assert!(!text.contains("## backend objects"));
+    }
+
+    #[test]
+    fn masking_hides_the_oui_in_every_spelling() {
+        let text = bundle().render(Redaction::MaskOui);
+        for leak in ["AA:BB:CC", "AA_BB_CC", "aa:bb:cc"] {
+            assert!(!text.contains(leak), "{leak} left in\n{text}");
+        }
+        assert!(text.contains("XX:XX:XX:DD:EE:FF"));
+        assert!(text.contains("bluez_card.XX_XX_XX_DD_EE_FF"));
+        assert!(text.contains("XX:XX:XX:dd:ee:ff"));
+        // Addresses other than the one asked about are masked too
+        assert!(!text.contains("11:22:33") && text.contains("XX:XX:XX:44:55:66"));
+
+        let text = bundle().render(Redaction::MaskAll);
+        assert!(!text.contains("DD:EE:FF") && !text.contains("DD_EE_FF"));
+        assert!(text.contains("XX_XX_XX_XX_XX_XX"));
+    }
+
+    #[test]
+    fn only_mac_shaped_tokens_are_masked() {
+        let redact = |text| Redaction::MaskAll.apply(text);
+        assert_eq!(
+            redact("bluez_output.AA_BB_CC_DD_EE_FF.1"),
+            "bluez_output.XX_XX_XX_XX_XX_XX.1"
+        );
+        assert_eq!(
+            redact("time 12:30:45, uuid 0000110b-0000"),
+            "time 12:30:45, uuid 0000110b-0000"
+        );
+        // Mixed separators or a longer hex run are no address, a trailing colon is fine
+        assert_eq!(redact("AA:BB_CC:DD:EE:FF"), "AA:BB_CC:DD:EE:FF");
+        assert_eq!(redact("AA:BB:CC:DD:EE:FF:00"), "AA:BB:CC:DD:EE:FF:00");
+        assert_eq!(
+            redact("device AA:BB:CC:DD:EE:FF: ok"),
+            "device XX:XX:XX:XX:XX:XX: ok"
+        );
+        assert_eq!(redact("é AA:BB:CC:DD:EE:FF"), "é XX:XX:XX:XX:XX:XX");
+        assert_eq!(
+            Redaction::Off.apply("AA:BB:CC:DD:EE:FF"),
+            "AA:BB:CC:DD:EE:FF"
+        );
+    }
+}
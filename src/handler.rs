@@ -3,10 +3,13 @@ use std::sync::atomic::Ordering;
 
 use crate::app::FocusedBlock;
 use crate::app::{App, AppResult};
+use crate::bluetooth;
 use crate::config::Config;
 use crate::event::Event;
 use crate::notification::{Notification, NotificationLevel};
+use crate::preferences;
 use crate::profile;
+use crate::rfkill;
 use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
 use futures::StreamExt;
 use tokio::sync::mpsc::UnboundedSender;
@@ -18,6 +21,7 @@ async fn toggle_connect(app: &mut App, sender: UnboundedSender<Event>) {
         let controller = &app.controllers[selected_controller];
         if let Some(index) = app.paired_devices_state.selected() {
             let addr = controller.paired_devices[index].addr;
+            let codec_preference = app.config.codec_preference.clone();
             match controller.adapter.device(addr) {
                 Ok(device) => {
                     tokio::spawn(async move {
@@ -41,13 +45,28 @@ async fn toggle_connect(app: &mut App, sender: UnboundedSender<Event>) {
                                         }
                                     }
                                 } else {
-                                    match device.connect().await {
+                                    match bluetooth::connect(&device).await {
                                         Ok(_) => {
                                             let _ = Notification::send(
                                                 "Device connected".into(),
                                                 NotificationLevel::Info,
                                                 sender.clone(),
                                             );
+
+                                            // Without a preference the negotiated codec is kept
+                                            if !codec_preference.is_empty()
+                                                && let Err(e) = profile::connect_preferred_codec(
+                                                    &addr,
+                                                    &codec_preference,
+                                                )
+                                                .await
+                                            {
+                                                let _ = Notification::send(
+                                                    e.into(),
+                                                    NotificationLevel::Warning,
+                                                    sender.clone(),
+                                                );
+                                            }
                                         }
                                         Err(e) => {
                                             let _ = Notification::send(
@@ -203,6 +222,8 @@ pub async fn handle_key_events(
                 app.focused_block = FocusedBlock::PairedDevices;
                 app.available_profiles.clear();
                 app.profile_state.select(None);
+                app.audio_device = None;
+                app.confirm_switch = None;
             }
             KeyCode::Char('j') | KeyCode::Down => {
                 if !app.available_profiles.is_empty() {
@@ -234,21 +255,79 @@ pub async fn handle_key_events(
                     app.profile_state.select(Some(i));
                 }
             }
+            KeyCode::Char('l') => {
+                if let Some(device) = &app.audio_device {
+                    let locked = !app.profile_locked;
+                    match profile::set_locked(&device.address, locked) {
+                        Ok(_) => {
+                            app.profile_locked = locked;
+                            let message = if locked {
+                                "Profile locked"
+                            } else {
+                                "Profile unlocked"
+                            };
+                            let _ = Notification::send(
+                                message.into(),
+                                NotificationLevel::Info,
+                                sender.clone(),
+                            );
+                        }
+                        Err(e) => {
+                            let _ = Notification::send(
+                                e.to_string().into(),
+                                NotificationLevel::Error,
+                                sender.clone(),
+                            );
+                        }
+                    }
+                }
+            }
             KeyCode::Enter | KeyCode::Char(' ') => {
                 if let Some(profile_idx) = app.profile_state.selected()
                     && let Some(ref device_id) = app.audio_device_id
                 {
                     let selected_profile = app.available_profiles[profile_idx].clone();
+
+                    if app.confirm_switch != Some(selected_profile.index)
+                        && app.audio_device.as_ref().is_some_and(|device| {
+                            profile::switch_would_disrupt(device, selected_profile.index)
+                        })
+                    {
+                        app.confirm_switch = Some(selected_profile.index);
+                        let _ = Notification::send(
+                            "A call is active, press again to switch and end it".into(),
+                            NotificationLevel::Warning,
+                            sender.clone(),
+                        );
+                        return Ok(());
+                    }
+
                     let device_id = device_id.clone();
+                    let audio_device = app.audio_device.clone();
+                    let address = audio_device.as_ref().map(|device| device.address);
                     let sender_clone = sender.clone();
 
                     tokio::spawn(async move {
-                        match profile::switch_profile(
-                            &device_id,
-                            selected_profile.index,
-                            &selected_profile.name,
-                        ) {
+                        let result = match &audio_device {
+                            Some(device) => {
+                                profile::switch_device_profile(device, &selected_profile, false)
+                            }
+                            None => profile::switch_profile(
+                                &device_id,
+                                selected_profile.index,
+                                &selected_profile.name,
+                                None,
+                                None,
+                            ),
+                        };
+                        match result {
                             Ok(_) => {
+                                if let Some(address) = address {
+                                    let _ = preferences::save_preferred_profile(
+                                        &address,
+                                        &selected_profile.name,
+                                    );
+                                }
                                 let _ = Notification::send(
                                     format!("Switched to {}", selected_profile.description).into(),
                                     NotificationLevel::Info,
@@ -270,6 +349,8 @@ pub async fn handle_key_events(
                     app.profile_state.select(None);
                     app.audio_device_id = None;
                     app.active_profile_index = None;
+                    app.audio_device = None;
+                    app.confirm_switch = None;
                 }
             }
             _ => {}
@@ -542,6 +623,7 @@ pub async fn handle_key_events(
                             app.spinner.active = true;
                             let adapter = controller.adapter.clone();
                             let is_scanning = controller.is_scanning.clone();
+                            let audio_only_scan = config.audio_only_scan;
                             tokio::spawn(async move {
                                 let _ = Notification::send(
                                     "Scanning started".into(),
@@ -549,13 +631,36 @@ pub async fn handle_key_events(
                                     sender.clone(),
                                 );
 
-                                match adapter.discover_devices().await {
-                                    Ok(mut discover) => {
-                                        while let Some(_evt) = discover.next().await {
+                                if audio_only_scan
+                                    && let Err(e) = bluetooth::set_discovery_filter(
+                                        &adapter,
+                                        bluetooth::audio_discovery_filter(),
+                                    )
+                                    .await
+                                {
+                                    let _ = Notification::send(
+                                        e.into(),
+                                        NotificationLevel::Warning,
+                                        sender.clone(),
+                                    );
+                                }
+
+                                match bluetooth::DiscoverySession::start(adapter).await {
+                                    Ok(mut discovery) => {
+                                        let mut devices = discovery.devices();
+                                        while devices.next().await.is_some() {
                                             if !is_scanning.load(Ordering::Relaxed) {
                                                 break;
                                             }
                                         }
+                                        drop(devices);
+                                        if let Ok(false) = discovery.stop().await {
+                                            let _ = Notification::send(
+                                                "Discovery is still on".into(),
+                                                NotificationLevel::Warning,
+                                                sender.clone(),
+                                            );
+                                        }
                                     }
                                     Err(e) => {
                                         let _ = Notification::send(
@@ -713,7 +818,17 @@ pub async fn handle_key_events(
                                         if let Some(index) = app.paired_devices_state.selected() {
                                             let device = &controller.paired_devices[index];
                                             match profile::get_audio_device(&device.addr) {
+                                                Some(audio_device) if audio_device.pending => {
+                                                    let _ = Notification::send(
+                                                        "Audio profiles are not available yet, try again in a moment".into(),
+                                                        NotificationLevel::Info,
+                                                        sender.clone(),
+                                                    );
+                                                }
                                                 Some(audio_device) if !audio_device.profiles.is_empty() => {
+                                                    app.profile_locked =
+                                                        profile::is_locked(&audio_device.address);
+                                                    app.audio_device = Some(audio_device.clone());
                                                     app.audio_device_id = Some(audio_device.id);
                                                     app.active_profile_index =
                                                         audio_device.active_profile_index;
@@ -734,6 +849,47 @@ pub async fn handle_key_events(
                                     }
                                 }
 
+                                KeyCode::Char(c)
+                                    if c == config.paired_device.volume_up
+                                        || c == config.paired_device.volume_down =>
+                                {
+                                    if let Some(selected_controller) =
+                                        app.controller_state.selected()
+                                    {
+                                        let controller = &app.controllers[selected_controller];
+                                        if let Some(index) = app.paired_devices_state.selected() {
+                                            let addr = controller.paired_devices[index].addr;
+                                            let delta = if c == config.paired_device.volume_up {
+                                                5
+                                            } else {
+                                                -5
+                                            };
+                                            let prefer_hw_volume = config.prefer_hw_volume;
+                                            tokio::spawn(async move {
+                                                match profile::change_volume(
+                                                    &addr,
+                                                    delta,
+                                                    prefer_hw_volume,
+                                                ) {
+                                                    Ok(msg) => {
+                                                        let _ = Notification::send(
+                                                            msg.into(),
+                                                            NotificationLevel::Info,
+                                                            sender.clone(),
+                                                        );
+                                                    }
+                                                    Err(e) => {
+                                                        let _ = Notification::send(
+                                                            e.into(),
+                                                            NotificationLevel::Error,
+                                                            sender.clone(),
+                                                        );
+                                                    }
+                                                }
+                                            });
+                                        }
+                                    }
+                                }
                                 _ => {}
                             }
                         }
@@ -840,8 +996,12 @@ pub async fn handle_key_events(
                                                                     );
                                                                 }
                                                                 Err(e) => {
+                                                                    let message = match rfkill::rfkill_state().hint() {
+                                                                        Some(hint) => hint.into(),
+                                                                        None => e.into(),
+                                                                    };
                                                                     let _ = Notification::send(
-                                                                        e.into(),
+                                                                        message,
                                                                         NotificationLevel::Error,
                                                                         sender.clone(),
                                                                     );
@@ -51,6 +51,13 @@ impl Help {
                         Span::from(config.paired_device.switch_profile.to_string()).bold(),
                         Span::from(" Profile"),
                         Span::from(" | "),
+                        Span::from(format!(
+                            "{}/{}",
+                            config.paired_device.volume_up, config.paired_device.volume_down
+                        ))
+                        .bold(),
+                        Span::from(" Volume"),
+                        Span::from(" | "),
                         Span::from("⇄").bold(),
                         Span::from(" Nav"),
                     ])]
@@ -79,6 +86,13 @@ impl Help {
                             Span::from(config.paired_device.switch_profile.to_string()).bold(),
                             Span::from(" Profile"),
                             Span::from(" | "),
+                            Span::from(format!(
+                                "{}/{}",
+                                config.paired_device.volume_up, config.paired_device.volume_down
+                            ))
+                            .bold(),
+                            Span::from(" Volume"),
+                            Span::from(" | "),
                             Span::from("k,").bold(),
                             Span::from("  Up"),
                             Span::from(" | "),
@@ -155,6 +169,9 @@ impl Help {
                     Span::from("↵ ").bold(),
                     Span::from(" Switch"),
                     Span::from(" | "),
+                    Span::from("l").bold(),
+                    Span::from(" Lock"),
+                    Span::from(" | "),
                     Span::from("Esc").bold(),
                     Span::from(" Cancel"),
                 ])]
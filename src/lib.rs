@@ -3,12 +3,15 @@ pub mod app;
 pub mod bluetooth;
 pub mod cli;
 pub mod config;
+pub mod diagnostics;
 pub mod event;
 pub mod favorite;
 pub mod handler;
 mod help;
 pub mod notification;
+pub mod preferences;
 pub mod profile;
+pub mod recent;
 pub mod requests;
 pub mod rfkill;
 pub mod spinner;
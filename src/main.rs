@@ -2,9 +2,10 @@ use bluetui::{
     app::{App, AppResult},
     cli,
     config::Config,
+    diagnostics,
     event::{Event, EventHandler},
     handler::handle_key_events,
-    rfkill,
+    profile, rfkill,
     tui::Tui,
 };
 use ratatui::{Terminal, backend::CrosstermBackend};
@@ -25,6 +26,26 @@ async fn main() -> AppResult<()> {
         None
     };
 
+    if let Some(path) = args.get_one::<PathBuf>("event-log")
+        && let Err(e) = profile::enable_event_log(path)
+    {
+        eprintln!("Can not open the event log: {e}");
+        exit(1);
+    }
+
+    if let Some(path) = args.get_one::<PathBuf>("mock-audio")
+        && let Err(e) = profile::enable_mock_backend(path)
+    {
+        eprintln!("Can not load the mock audio devices: {e}");
+        exit(1);
+    }
+
+    if args.contains_id("diagnostics") {
+        let addr = args.get_one::<bluer::Address>("diagnostics");
+        println!("{}", diagnostics::diagnostics_bundle(addr));
+        return Ok(());
+    }
+
     rfkill::check()?;
 
     let config = Arc::new(Config::new(config_file_path));
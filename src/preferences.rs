@@ -0,0 +1,116 @@
+use crate::app::AppResult;
+use anyhow::Context;
+use bluer::Address;
+use clap::crate_name;
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+    str::FromStr,
+};
+
+fn preferred_profiles_path() -> AppResult<PathBuf> {
+    let data_dir = dirs::data_dir()
+        .context("unable to find data_dir")?
+        .join(crate_name!());
+
+    Ok(data_dir.join("profiles.txt"))
+}
+
+fn locked_devices_path() -> AppResult<PathBuf> {
+    let data_dir = dirs::data_dir()
+        .context("unable to find data_dir")?
+        .join(crate_name!());
+
+    Ok(data_dir.join("locked.txt"))
+}
+
+/// Profile the user last picked for each device, one `address profile-name` per line.
+pub fn read_preferred_profiles() -> AppResult<HashMap<Address, String>> {
+    let contents = std::fs::read_to_string(preferred_profiles_path()?)
+        .context("unable to open preferred profiles file")?;
+
+    Ok(contents
+        .lines()
+        .filter_map(|line| {
+            let (addr, profile) = line.split_once(' ')?;
+            Some((Address::from_str(addr).ok()?, profile.trim().to_string()))
+        })
+        .collect())
+}
+
+pub fn preferred_profile(addr: &Address) -> Option<String> {
+    read_preferred_profiles().ok()?.remove(addr)
+}
+
+pub fn save_preferred_profile(addr: &Address, profile: &str) -> AppResult<()> {
+    let mut profiles = read_preferred_profiles().unwrap_or_default();
+    profiles.insert(*addr, profile.to_string());
+
+    let file_path = preferred_profiles_path()?;
+
+    let mut contents = profiles
+        .iter()
+        .map(|(addr, profile)| format!("{addr} {profile}"))
+        .collect::<Vec<_>>();
+    contents.sort();
+
+    if let Some(data_dir) = file_path.parent()
+        && !data_dir.exists()
+    {
+        std::fs::create_dir_all(data_dir)
+            .context("unable to create parent dir(s) to preferred profiles file")?;
+    }
+
+    std::fs::write(file_path, contents.join("\n"))
+        .context("error writing preferred profiles file")?;
+
+    Ok(())
+}
+
+/// Devices pinned to their current profile, one address per line.
+pub fn locked_devices() -> HashSet<Address> {
+    let Ok(contents) = locked_devices_path().and_then(|path| {
+        std::fs::read_to_string(path).context("unable to open locked devices file")
+    }) else {
+        return HashSet::new();
+    };
+
+    contents
+        .lines()
+        .filter_map(|line| Address::from_str(line.trim()).ok())
+        .collect()
+}
+
+pub fn is_locked(addr: &Address) -> bool {
+    locked_devices().contains(addr)
+}
+
+/// Pin the device to its profile, or release it. Locked devices refuse profile
+/// switches unless forced.
+pub fn set_locked(addr: &Address, locked: bool) -> AppResult<()> {
+    let mut devices = locked_devices();
+    if locked {
+        devices.insert(*addr);
+    } else {
+        devices.remove(addr);
+    }
+
+    let file_path = locked_devices_path()?;
+
+    let mut contents = devices
+        .iter()
+        .map(|addr| addr.to_string())
+        .collect::<Vec<_>>();
+    contents.sort();
+
+    if let Some(data_dir) = file_path.parent()
+        && !data_dir.exists()
+    {
+        std::fs::create_dir_all(data_dir)
+            .context("unable to create parent dir(s) to locked devices file")?;
+    }
+
+    std::fs::write(file_path, contents.join("\n")).context("error writing locked devices file")?;
+
+    Ok(())
+}
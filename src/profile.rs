@@ -1,6 +1,26 @@
+pub(crate) mod bluez;
+pub mod event_log;
+mod mock;
+mod watcher;
+
+pub use event_log::{disable_event_log, enable_event_log};
+pub use mock::enable_mock_backend;
+pub use watcher::ProfileWatcher;
+
+use crate::{app::AppResult, bluetooth, rfkill::RfkillState};
 use bluer::Address;
+use event_log::Event;
 use serde::Deserialize;
-use std::process::Command;
+use std::{
+    collections::BTreeMap,
+    fmt, io,
+    io::{BufRead, BufReader},
+    process::{Child, Command, Output, Stdio},
+    str::FromStr,
+    sync::{Mutex, mpsc},
+    thread,
+    time::{Duration, Instant},
+};
 
 #[derive(Debug, Clone)]
 pub struct AudioProfile {
@@ -8,17 +28,97 @@ pub struct AudioProfile {
     pub name: String,
     pub description: String,
     pub available: bool,
+    pub category: ProfileCategory,
 }
 
 #[derive(Debug, Clone)]
 pub struct AudioDevice {
     pub id: AudioDeviceId,
+    pub address: Address,
+    pub name: Option<String>,
     pub profiles: Vec<AudioProfile>,
     pub active_profile_index: Option<u32>,
+    /// Codec negotiated for the active profile, e.g. `ldac`
+    pub codec: Option<String>,
+    /// Output volume in percent
+    pub volume: Option<u8>,
+    /// Format of the sink the active profile created. Backends only build the sink
+    /// of the active profile, so the other profiles' formats are unknown.
+    pub format: Option<AudioFormat>,
+    /// The card exists but the backend has not listed its profiles yet, which
+    /// happens for a moment after connecting. `profiles` is empty meanwhile.
+    pub pending: bool,
+}
+
+impl AudioDevice {
+    /// The active profile, looked up by index so a stale index yields `None`.
+    pub fn active_profile(&self) -> Option<&AudioProfile> {
+        let index = self.active_profile_index?;
+        self.profiles.iter().find(|p| p.index == index)
+    }
+
+    pub fn active_profile_name(&self) -> Option<&str> {
+        self.active_profile().map(|p| p.name.as_str())
+    }
+
+    fn pending(id: AudioDeviceId, address: Address, name: Option<String>) -> Self {
+        Self {
+            id,
+            address,
+            name,
+            profiles: Vec::new(),
+            active_profile_index: None,
+            codec: None,
+            volume: None,
+            format: None,
+            pending: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AudioFormat {
+    pub channels: Option<u8>,
+    /// `mono`, `stereo`, `5.1`, ... when the channel positions match a common layout
+    pub layout: Option<String>,
+}
+
+impl AudioFormat {
+    /// Build from the channel positions as the backend names them (`FL,FR,LFE` or
+    /// `front-left,front-right,lfe`).
+    fn from_positions(channels: Option<u8>, positions: &[String]) -> Option<Self> {
+        let channels =
+            channels.or_else(|| u8::try_from(positions.len()).ok().filter(|n| *n > 0))?;
+
+        let lfe = positions.iter().any(|p| p.eq_ignore_ascii_case("lfe"));
+        let layout = match (channels, lfe) {
+            (1, _) => Some("mono"),
+            (2, _) => Some("stereo"),
+            (3, true) => Some("2.1"),
+            (4, false) => Some("quad"),
+            (6, true) => Some("5.1"),
+            (8, true) => Some("7.1"),
+            _ => None,
+        };
+
+        Some(Self {
+            channels: Some(channels),
+            layout: layout.map(str::to_string),
+        })
+    }
+
+    /// Short label for the TUI: `2ch`, `5.1`, ...
+    pub fn label(&self) -> Option<String> {
+        match (self.channels, self.layout.as_deref()) {
+            (Some(n), Some(layout)) if n > 2 => Some(layout.to_string()),
+            (Some(n), _) => Some(format!("{n}ch")),
+            (None, layout) => layout.map(str::to_string),
+        }
+    }
 }
 
 /// Device identifier — varies by backend.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum AudioDeviceId {
     /// PipeWire object id (used with `wpctl set-profile <id> <index>`)
     Pipewire(u32),
@@ -26,245 +126,4276 @@ pub enum AudioDeviceId {
     Pulseaudio(String),
 }
 
-// ── public entry points ────────────────────────────────────────────
+impl AudioDeviceId {
+    fn backend_name(&self) -> &'static str {
+        match self {
+            AudioDeviceId::Pipewire(_) => "pipewire",
+            AudioDeviceId::Pulseaudio(_) => "pulseaudio",
+        }
+    }
 
-/// Try PipeWire first, then PulseAudio.
-pub fn get_audio_device(addr: &Address) -> Option<AudioDevice> {
-    get_pipewire_device(addr).or_else(|| get_pulseaudio_device(addr))
+    /// Start an event log entry for a change made through this device. Without an
+    /// `address` it is taken from the PulseAudio card name, PipeWire ids carry none.
+    fn event(&self, kind: &'static str, address: Option<Address>, new: impl Into<String>) -> Event {
+        let event = Event::new(kind, self.backend_name(), new);
+        let address = address.or_else(|| match self {
+            AudioDeviceId::Pulseaudio(card) => card
+                .strip_prefix("bluez_card.")
+                .and_then(address_from_bluez_format),
+            AudioDeviceId::Pipewire(_) => None,
+        });
+        match address {
+            Some(addr) => event.address(addr),
+            None => event,
+        }
+    }
 }
 
-/// Switch profile using whichever backend owns the device.
-pub fn switch_profile(device: &AudioDeviceId, profile_index: u32, profile_name: &str) -> Result<String, String> {
-    match device {
-        AudioDeviceId::Pipewire(id) => switch_pipewire_profile(*id, profile_index),
-        AudioDeviceId::Pulseaudio(card) => switch_pulseaudio_profile(card, profile_name),
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProfileError {
+    /// The backend CLI (`wpctl`, `pactl`, ...) is not on `$PATH`
+    BackendNotInstalled(&'static str),
+    /// The backend CLI could not be started for another reason
+    Io {
+        command: &'static str,
+        message: String,
+    },
+    /// The backend CLI ran and reported a failure
+    CommandFailed {
+        command: &'static str,
+        stderr: String,
+    },
+    /// No PipeWire object with this id exists
+    DeviceNotFound(u32),
+    /// The PipeWire id belongs to a node rather than a device; `wpctl set-profile`
+    /// only accepts device ids
+    NotADevice { id: u32, media_class: String },
+    /// Neither backend has a card for this Bluetooth address
+    NoAudioDevice(Address),
+    /// A BlueZ D-Bus call failed
+    Dbus(String),
+    /// A bluer call failed
+    Bluetooth {
+        kind: bluer::ErrorKind,
+        message: String,
+    },
+    /// The device has no profile pinning this codec
+    CodecUnavailable(String),
+    /// The device does not offer a profile with this name
+    ProfileUnavailable(String),
+    /// No built-in EQ preset with this name
+    UnknownPreset(String),
+    /// The user pinned the device to its profile
+    Locked(Address),
+    /// BlueZ knows no bluetooth adapter
+    NoAdapter,
+    /// rfkill keeps the radio off
+    RadioBlocked(RfkillState),
+    /// Re-applying a profile switched the device off and could not switch it back
+    LeftOff { profile: String, reason: String },
+    /// PulseAudio only takes this setting for every device at once, when it starts
+    RestartRequired(&'static str),
+}
+
+impl fmt::Display for ProfileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProfileError::BackendNotInstalled(command) => write!(f, "{command} is not installed"),
+            ProfileError::Io { command, message } => {
+                write!(f, "Failed to run {command}: {message}")
+            }
+            ProfileError::CommandFailed { command, stderr } => {
+                write!(f, "{command} failed: {stderr}")
+            }
+            ProfileError::DeviceNotFound(id) => write!(f, "No PipeWire device with id {id}"),
+            ProfileError::NotADevice { id, media_class } => write!(
+                f,
+                "PipeWire object {id} is a node ({media_class}), not a device"
+            ),
+            ProfileError::NoAudioDevice(addr) => write!(f, "No audio device for {addr}"),
+            ProfileError::Dbus(message) => write!(f, "D-Bus error: {message}"),
+            ProfileError::Bluetooth { message, .. } => write!(f, "{message}"),
+            ProfileError::Locked(addr) => write!(f, "{addr} is locked to its current profile"),
+            ProfileError::NoAdapter => write!(f, "No bluetooth adapter found"),
+            ProfileError::RadioBlocked(state) => write!(f, "Bluetooth is {state}"),
+            ProfileError::CodecUnavailable(codec) => {
+                write!(f, "Codec {} is not available", codec_label(codec))
+            }
+            ProfileError::ProfileUnavailable(name) => write!(f, "Profile {name} is not available"),
+            ProfileError::UnknownPreset(name) => write!(f, "Unknown EQ preset {name}"),
+            ProfileError::LeftOff { profile, reason } => write!(
+                f,
+                "Could not switch back to {profile}, the device was left off: {reason}"
+            ),
+            ProfileError::RestartRequired(setting) => write!(
+                f,
+                "PulseAudio sets {setting} for all devices at once and needs a restart"
+            ),
+        }
     }
 }
 
-// ── PipeWire backend ───────────────────────────────────────────────
+impl std::error::Error for ProfileError {}
 
-#[derive(Deserialize)]
-struct PwDumpEntry {
-    id: u32,
-    #[serde(default)]
-    info: Option<PwInfo>,
+impl From<bluer::Error> for ProfileError {
+    fn from(err: bluer::Error) -> Self {
+        ProfileError::Bluetooth {
+            message: err.to_string(),
+            kind: err.kind,
+        }
+    }
 }
 
-#[derive(Deserialize)]
-struct PwInfo {
-    #[serde(default)]
-    props: Option<PwProps>,
-    #[serde(default)]
-    params: Option<PwParams>,
+impl ProfileError {
+    fn spawn(command: &'static str, err: io::Error) -> Self {
+        if err.kind() == io::ErrorKind::NotFound {
+            ProfileError::BackendNotInstalled(command)
+        } else {
+            ProfileError::Io {
+                command,
+                message: err.to_string(),
+            }
+        }
+    }
+
+    /// What the user can do about the error, shown under the message.
+    pub fn hint(&self) -> Option<&'static str> {
+        match self {
+            ProfileError::BackendNotInstalled(_) => {
+                Some("Install pipewire-utils (wpctl, pw-dump) or pulseaudio-utils (pactl)")
+            }
+            ProfileError::DeviceNotFound(_) => {
+                Some("The device may have disconnected, reopen the list and try again")
+            }
+            ProfileError::NoAudioDevice(_) => Some(
+                "Connect the device and check that bluetooth audio support is loaded \
+                 (WirePlumber's bluez5 monitor or module-bluez5-discover)",
+            ),
+            // Only when BlueZ itself is missing from the bus
+            ProfileError::Dbus(message)
+                if message.contains("ServiceUnknown") || message.contains("not provided by") =>
+            {
+                Some("Start bluetooth.service and power the adapter on")
+            }
+            ProfileError::Bluetooth { kind, message } => bluetooth_hint(kind, message),
+            ProfileError::CodecUnavailable(_) => {
+                Some("Pick one of the codecs the device lists, the backend may lack the others")
+            }
+            ProfileError::ProfileUnavailable(_) => {
+                Some("Reconnect the device, it only offers the profiles it advertised")
+            }
+            ProfileError::UnknownPreset(_) => Some("Use one of the built-in presets or \"off\""),
+            ProfileError::Locked(_) => Some("Unlock it with `l` in the profile selector"),
+            ProfileError::NoAdapter => Some("Start bluetooth.service and plug the adapter in"),
+            ProfileError::RadioBlocked(RfkillState::SoftBlocked) => {
+                Some("Run `sudo rfkill unblock bluetooth`")
+            }
+            ProfileError::RadioBlocked(RfkillState::HardBlocked) => {
+                Some("Check the hardware switch or the BIOS")
+            }
+            ProfileError::LeftOff { .. } => {
+                Some("Pick a profile in the selector to turn the device back on")
+            }
+            ProfileError::RestartRequired(_) => Some(
+                "Set avrcp_absolute_volume on module-bluez5-discover in /etc/pulse/default.pa, \
+                 then restart PulseAudio with `pulseaudio -k`",
+            ),
+            ProfileError::Io { .. }
+            | ProfileError::CommandFailed { .. }
+            | ProfileError::NotADevice { .. }
+            | ProfileError::Dbus(_)
+            | ProfileError::RadioBlocked(RfkillState::Unblocked) => None,
+        }
+    }
 }
 
-#[derive(Deserialize)]
-struct PwProps {
-    #[serde(rename = "api.bluez5.address")]
-    bluez5_address: Option<String>,
+/// Hint for a failed bluer call. Only a lost or missing bluetoothd points at
+/// bluetooth.service; a device that is out of range or refused to pair needs
+/// something else.
+fn bluetooth_hint(kind: &bluer::ErrorKind, message: &str) -> Option<&'static str> {
+    use bluer::{ErrorKind, InternalErrorKind};
+
+    match kind {
+        ErrorKind::Internal(InternalErrorKind::DBus(_) | InternalErrorKind::DBusConnectionLost) => {
+            Some("Start bluetooth.service and power the adapter on")
+        }
+        ErrorKind::NotReady => Some("Power the adapter on"),
+        ErrorKind::AuthenticationFailed
+        | ErrorKind::AuthenticationRejected
+        | ErrorKind::AuthenticationCanceled
+        | ErrorKind::AuthenticationTimeout => {
+            Some("Remove the device, put it in pairing mode and pair it again")
+        }
+        ErrorKind::ConnectionAttemptFailed => {
+            Some("Turn the device on and bring it in range, then try again")
+        }
+        ErrorKind::Failed
+            if message.to_ascii_lowercase().contains("page timeout")
+                || message.contains("page-timeout") =>
+        {
+            Some("Turn the device on and bring it in range, then try again")
+        }
+        ErrorKind::DoesNotExist | ErrorKind::NotFound => {
+            Some("The device is gone, scan for it again")
+        }
+        _ => None,
+    }
 }
 
-#[derive(Deserialize)]
-struct PwParams {
-    #[serde(rename = "EnumProfile", default)]
-    enum_profile: Vec<PwEnumProfile>,
-    #[serde(rename = "Profile", default)]
-    profile: Vec<PwActiveProfile>,
+/// Broad family a profile belongs to, derived from its name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProfileKind {
+    /// A2DP playback (`a2dp-sink`, `a2dp-sink-ldac`, ...)
+    A2dp,
+    /// Headset / hands-free head unit (`headset-head-unit`, `handsfree_head_unit`, ...)
+    HspHfp,
+    /// Audio gateway role (`headset-audio-gateway`, ...)
+    AudioGateway,
+    Off,
+    Other,
 }
 
-#[derive(Deserialize)]
-struct PwEnumProfile {
-    index: u32,
-    #[serde(default)]
-    name: Option<String>,
-    #[serde(default)]
-    description: Option<String>,
-    #[serde(default)]
-    available: Option<String>,
+/// Which directions of audio a profile carries, from the sinks and sources the
+/// backend says it creates.
+///
+/// Ordered the way the selector lists its sections.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ProfileCategory {
+    /// Playback only (A2DP)
+    Output,
+    /// Playback and capture (HSP/HFP), what calls need
+    Duplex,
+    /// Capture only
+    Input,
+    Off,
 }
 
-#[derive(Deserialize)]
-struct PwActiveProfile {
-    index: u32,
+impl ProfileCategory {
+    fn from_counts(sinks: u32, sources: u32) -> Self {
+        match (sinks > 0, sources > 0) {
+            (true, true) => ProfileCategory::Duplex,
+            (true, false) => ProfileCategory::Output,
+            (false, true) => ProfileCategory::Input,
+            (false, false) => ProfileCategory::Off,
+        }
+    }
+
+    /// Guess for backends that do not report the sinks/sources of a profile.
+    fn from_kind(kind: ProfileKind) -> Self {
+        match kind {
+            ProfileKind::A2dp | ProfileKind::Other => ProfileCategory::Output,
+            ProfileKind::HspHfp | ProfileKind::AudioGateway => ProfileCategory::Duplex,
+            ProfileKind::Off => ProfileCategory::Off,
+        }
+    }
+
+    pub fn has_input(&self) -> bool {
+        matches!(self, ProfileCategory::Input | ProfileCategory::Duplex)
+    }
+
+    /// Section header for the profile selector.
+    pub fn label(&self) -> &'static str {
+        match self {
+            ProfileCategory::Output => "High Quality",
+            ProfileCategory::Duplex => "Communication",
+            ProfileCategory::Input => "Input",
+            ProfileCategory::Off => "Off",
+        }
+    }
 }
 
-fn address_to_bluez_format(addr: &Address) -> String {
-    addr.to_string().replace(":", "_")
+impl ProfileKind {
+    pub fn from_profile_name(name: &str) -> Self {
+        let name = name.replace('_', "-");
+        if name == "off" {
+            ProfileKind::Off
+        } else if name.starts_with("a2dp") {
+            ProfileKind::A2dp
+        } else if name.contains("audio-gateway") {
+            ProfileKind::AudioGateway
+        } else if name.starts_with("headset") || name.starts_with("handsfree") {
+            ProfileKind::HspHfp
+        } else {
+            ProfileKind::Other
+        }
+    }
+
+    /// The profile a device advertising `uuid` can be put in, if it is an audio one.
+    fn from_uuid(uuid: &str) -> Option<Self> {
+        match uuid.get(..8)? {
+            // Audio Sink
+            "0000110b" => Some(ProfileKind::A2dp),
+            // Headset, Handsfree
+            "00001108" | "0000111e" => Some(ProfileKind::HspHfp),
+            // Headset AG, Handsfree AG
+            "00001112" | "0000111f" => Some(ProfileKind::AudioGateway),
+            _ => None,
+        }
+    }
+
+    /// Rank the backends give a profile when picking one on connect, higher first.
+    fn connect_priority(&self) -> u8 {
+        match self {
+            ProfileKind::A2dp => 3,
+            ProfileKind::HspHfp => 2,
+            ProfileKind::AudioGateway => 1,
+            ProfileKind::Off | ProfileKind::Other => 0,
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            ProfileKind::A2dp => "A2DP",
+            ProfileKind::HspHfp => "HSP/HFP",
+            ProfileKind::AudioGateway => "AG",
+            ProfileKind::Off => "Off",
+            ProfileKind::Other => "Other",
+        }
+    }
 }
 
-fn get_pipewire_device(addr: &Address) -> Option<AudioDevice> {
-    let output = Command::new("pw-dump").output().ok()?;
-    if !output.status.success() {
-        return None;
+impl AudioProfile {
+    pub fn kind(&self) -> ProfileKind {
+        ProfileKind::from_profile_name(&self.name)
     }
 
-    let entries: Vec<PwDumpEntry> = serde_json::from_slice(&output.stdout).ok()?;
-    let addr_str = address_to_bluez_format(addr);
+    /// Codec pinned by this profile, if any.
+    ///
+    /// PipeWire exposes one profile per codec (`a2dp-sink-ldac`) and repeats it in the
+    /// description (`High Fidelity Playback (A2DP Sink, codec LDAC)`).
+    pub fn codec(&self) -> Option<String> {
+        if let Some((_, rest)) = self.description.split_once("codec ") {
+            let codec = rest.trim_end_matches(')').trim();
+            if !codec.is_empty() {
+                return Some(codec.to_lowercase().replace([' ', '-'], "_"));
+            }
+        }
 
-    for entry in &entries {
-        let Some(info) = &entry.info else { continue };
-        let Some(props) = &info.props else { continue };
-        let Some(ref bluez_addr) = props.bluez5_address else {
-            continue;
-        };
+        self.split_codec_suffix()
+            .1
+            .map(|codec| codec.replace('-', "_"))
+    }
 
-        if bluez_addr.replace(":", "_") != addr_str {
-            continue;
+    /// Name without the codec suffix, `a2dp-sink-ldac` → `a2dp-sink`.
+    pub fn base_name(&self) -> String {
+        self.split_codec_suffix().0
+    }
+
+    fn split_codec_suffix(&self) -> (String, Option<String>) {
+        let name = self.name.replace('_', "-");
+        for base in CODEC_PROFILE_BASES {
+            if let Some(codec) = name.strip_prefix(base).and_then(|r| r.strip_prefix('-'))
+                && !codec.is_empty()
+            {
+                return (base.to_string(), Some(codec.to_string()));
+            }
         }
+        (name, None)
+    }
+}
 
-        let Some(params) = &info.params else { continue };
-        if params.enum_profile.is_empty() {
-            continue;
+/// The device's profiles split by [`ProfileCategory`], each group in the backend's
+/// order. The active profile keeps its index, so
+/// [`AudioDevice::active_profile_index`] still finds it inside its group.
+pub fn grouped_profiles(device: &AudioDevice) -> BTreeMap<ProfileCategory, Vec<AudioProfile>> {
+    let mut groups: BTreeMap<ProfileCategory, Vec<AudioProfile>> = BTreeMap::new();
+    for profile in &device.profiles {
+        groups
+            .entry(profile.category)
+            .or_default()
+            .push(profile.clone());
+    }
+    groups
+}
+
+/// Shorten `label` to at most `max` characters, ending it with `…` when cut.
+///
+/// Counts and cuts on `char`s, so multibyte text is never split mid-sequence.
+pub fn truncate_profile_label(label: &str, max: usize) -> String {
+    if label.chars().count() <= max {
+        return label.to_string();
+    }
+    if max == 0 {
+        return String::new();
+    }
+
+    let mut truncated: String = label.chars().take(max - 1).collect();
+    truncated.push('…');
+    truncated
+}
+
+/// Run a backend CLI to completion; every subprocess the module spawns goes through
+/// here or [`spawn_piped`].
+fn run(program: &'static str, args: &[&str]) -> io::Result<Output> {
+    #[cfg(feature = "tracing")]
+    let _span = tracing::debug_span!("backend_command", program, ?args).entered();
+
+    Command::new(program).args(args).output()
+}
+
+/// Start a backend CLI whose stdout is read as it comes.
+fn spawn_piped(program: &'static str, args: &[&str]) -> io::Result<Child> {
+    #[cfg(feature = "tracing")]
+    let _span = tracing::debug_span!("backend_command", program, ?args).entered();
+
+    Command::new(program)
+        .args(args)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+}
+
+/// Exit code and output of a backend command, kept whether it succeeded or not.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BackendResult {
+    /// `-1` when the process was killed by a signal
+    pub code: i32,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+impl From<Output> for BackendResult {
+    fn from(output: Output) -> Self {
+        Self {
+            code: output.status.code().unwrap_or(-1),
+            stdout: String::from_utf8_lossy(&output.stdout).to_string(),
+            stderr: String::from_utf8_lossy(&output.stderr).to_string(),
         }
+    }
+}
 
-        let profiles: Vec<AudioProfile> = params
-            .enum_profile
-            .iter()
-            .filter(|p| p.name.as_deref() != Some("off"))
-            .map(|p| AudioProfile {
-                index: p.index,
-                name: p.name.clone().unwrap_or_default(),
-                description: p.description.clone().unwrap_or_default(),
-                available: p.available.as_deref() == Some("yes"),
+impl BackendResult {
+    pub fn success(&self) -> bool {
+        self.code == 0
+    }
+
+    /// Turn a non zero exit code of `command` into [`ProfileError::CommandFailed`].
+    fn check(self, command: &'static str) -> Result<Self, ProfileError> {
+        if self.success() {
+            Ok(self)
+        } else {
+            Err(ProfileError::CommandFailed {
+                command,
+                stderr: self.stderr,
             })
-            .filter(|p| p.available)
-            .collect();
+        }
+    }
+}
+
+/// Run a backend CLI, only failing when it cannot be started.
+fn run_raw(program: &'static str, args: &[&str]) -> Result<BackendResult, ProfileError> {
+    run(program, args)
+        .map(BackendResult::from)
+        .map_err(|e| ProfileError::spawn(program, e))
+}
 
-        let active_profile_index = params.profile.first().map(|p| p.index);
+/// Run a backend CLI that is expected to succeed.
+fn run_backend(program: &'static str, args: &[&str]) -> Result<(), ProfileError> {
+    run_raw(program, args)?.check(program).map(|_| ())
+}
 
-        return Some(AudioDevice {
-            id: AudioDeviceId::Pipewire(entry.id),
-            profiles,
-            active_profile_index,
-        });
+// ── public entry points ────────────────────────────────────────────
+
+/// Try PipeWire first, then PulseAudio.
+#[cfg_attr(feature = "tracing", tracing::instrument(skip_all, fields(addr = %addr)))]
+pub fn get_audio_device(addr: &Address) -> Option<AudioDevice> {
+    if let Some(devices) = mock::devices() {
+        return devices.into_iter().find(|d| d.address == *addr);
     }
 
-    None
+    get_pipewire_device(addr).or_else(|| get_pulseaudio_device(addr))
 }
 
-fn switch_pipewire_profile(device_id: u32, profile_index: u32) -> Result<String, String> {
-    let output = Command::new("wpctl")
-        .args([
-            "set-profile",
-            &device_id.to_string(),
-            &profile_index.to_string(),
-        ])
-        .output()
-        .map_err(|e| format!("Failed to run wpctl: {e}"))?;
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    Pipewire,
+    Pulseaudio,
+    /// Fake devices loaded by [`enable_mock_backend`]
+    Mock,
+}
+
+/// Library versions reported by each backend CLI, `None` when it is not installed.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BackendVersions {
+    pub pipewire: Option<String>,
+    pub pulseaudio: Option<String>,
+}
+
+static BACKEND_VERSIONS: Mutex<Option<BackendVersions>> = Mutex::new(None);
 
-    if output.status.success() {
-        Ok("Profile switched".to_string())
+/// Backend versions, probed with `--version` on first use and cached afterwards.
+pub fn backend_versions() -> BackendVersions {
+    cached_backend_versions(&BACKEND_VERSIONS, || BackendVersions {
+        pipewire: probe_version("pw-dump"),
+        pulseaudio: probe_version("pactl"),
+    })
+}
+
+fn cached_backend_versions(
+    cache: &Mutex<Option<BackendVersions>>,
+    probe: impl FnOnce() -> BackendVersions,
+) -> BackendVersions {
+    let mut cached = cache.lock().unwrap_or_else(|e| e.into_inner());
+    cached.get_or_insert_with(probe).clone()
+}
+
+/// Drop the cached versions so the next call probes again, e.g. after installing a backend.
+pub fn invalidate_backend_versions() {
+    *BACKEND_VERSIONS.lock().unwrap_or_else(|e| e.into_inner()) = None;
+}
+
+/// PipeWire when its tools are installed, otherwise PulseAudio.
+pub fn detect_backend() -> Option<Backend> {
+    if mock::is_active() {
+        return Some(Backend::Mock);
+    }
+
+    let versions = backend_versions();
+    if versions.pipewire.is_some() {
+        Some(Backend::Pipewire)
+    } else if versions.pulseaudio.is_some() {
+        Some(Backend::Pulseaudio)
     } else {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        Err(format!("wpctl failed: {stderr}"))
+        None
     }
 }
 
-// ── PulseAudio backend ─────────────────────────────────────────────
-
-fn get_pulseaudio_device(addr: &Address) -> Option<AudioDevice> {
-    let output = Command::new("pactl")
-        .args(["--format=json", "list", "cards"])
-        .output()
-        .ok()?;
+fn probe_version(program: &'static str) -> Option<String> {
+    let output = run(program, &["--version"]).ok()?;
     if !output.status.success() {
         return None;
     }
 
-    let cards: Vec<PaCard> = serde_json::from_slice(&output.stdout).ok()?;
-    let addr_str = address_to_bluez_format(addr);
+    parse_version(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// Extract the version from `--version` output:
+///
+/// ```text
+/// pactl 16.1
+/// Compiled with libpulse 16.1.0
+/// Linked with libpulse 16.1.0
+/// ```
+///
+/// The linked library version wins since it is what actually runs.
+fn parse_version(text: &str) -> Option<String> {
+    let is_version = |word: &&str| {
+        word.starts_with(|c: char| c.is_ascii_digit())
+            && word.chars().all(|c| c.is_ascii_digit() || c == '.')
+    };
 
-    for card in &cards {
-        // Match by bluez5 address in properties
-        let card_addr = card
-            .properties
-            .get("api.bluez5.address")
-            .or_else(|| card.properties.get("device.string"))
-            .map(|s| s.replace(":", "_"));
+    let linked = text
+        .lines()
+        .find(|l| l.trim_start().starts_with("Linked with"))
+        .and_then(|l| l.split_whitespace().rev().find(is_version));
 
-        // Also try matching via card name (bluez_card.XX_XX_XX_XX_XX_XX)
-        let name_matches = card.name.contains(&addr_str);
-        let addr_matches = card_addr.as_deref() == Some(&addr_str);
+    linked
+        .or_else(|| text.split_whitespace().find(is_version))
+        .map(str::to_string)
+}
 
-        if !name_matches && !addr_matches {
-            continue;
-        }
+/// Every Bluetooth audio device the running backend has a card for.
+pub fn all_audio_devices() -> Vec<AudioDevice> {
+    if let Some(devices) = mock::devices() {
+        return devices;
+    }
 
-        let mut profiles: Vec<AudioProfile> = Vec::new();
-        let mut active_profile_index: Option<u32> = None;
+    if let Some(entries) = pipewire_snapshot() {
+        return parse_pipewire_devices(&entries);
+    }
 
-        for (idx, pa_profile) in card.profiles.iter().enumerate() {
-            if pa_profile.name == "off" {
-                continue;
-            }
-            let available = pa_profile.available;
-            let profile = AudioProfile {
-                index: idx as u32,
-                name: pa_profile.name.clone(),
-                description: pa_profile.description.clone(),
-                available,
-            };
-            if available {
-                profiles.push(profile);
-            }
-        }
+    let Some(cards) = pulseaudio_cards() else {
+        return Vec::new();
+    };
+    let sinks = pulseaudio_sinks();
+    cards
+        .iter()
+        .filter_map(|card| pulseaudio_device(card, &sinks))
+        .collect()
+}
 
-        // Find active profile
-        if let Some(ref active_name) = card.active_profile {
-            for (idx, p) in profiles.iter().enumerate() {
-                if p.name == *active_name {
-                    active_profile_index = Some(idx as u32);
-                    break;
-                }
-            }
-        }
+/// Number of streams currently playing to or recording from the device.
+pub fn active_stream_count(device: &AudioDevice) -> usize {
+    if mock::is_active() {
+        return 0;
+    }
 
-        if profiles.is_empty() {
-            continue;
+    match &device.id {
+        AudioDeviceId::Pipewire(id) => {
+            pipewire_snapshot().map_or(0, |entries| pipewire_stream_count(&entries, *id))
         }
+        AudioDeviceId::Pulseaudio(_) => pulseaudio_stream_count(&device.address),
+    }
+}
+
+/// Whether moving to `target` (a profile index) would drop a live call: the device is
+/// on a duplex profile with streams running and the target has no microphone.
+pub fn switch_would_disrupt(device: &AudioDevice, target: u32) -> bool {
+    disrupts_call(device, target, || active_stream_count(device))
+}
+
+/// [`switch_would_disrupt`] with the stream count left to the caller, only asked for
+/// when the profiles alone could end a call.
+fn disrupts_call(device: &AudioDevice, target: u32, stream_count: impl FnOnce() -> usize) -> bool {
+    let category = |index| {
+        device
+            .profiles
+            .iter()
+            .find(|p| Some(p.index) == index)
+            .map(|p| p.category)
+    };
+
+    category(device.active_profile_index) == Some(ProfileCategory::Duplex)
+        && !category(Some(target)).is_some_and(|c| c.has_input())
+        && stream_count() > 0
+}
+
+/// Whether the voice (SCO) link is up. `None` when the device is not on a
+/// call-capable profile.
+///
+/// This is not the BlueZ transport state: the nodes of a headset profile only run
+/// while the backend holds the SCO link open, so a running node stands in for it.
+pub fn sco_active(addr: &Address) -> Option<bool> {
+    // Fake devices never carry audio
+    if let Some(devices) = mock::devices() {
+        let device = devices.into_iter().find(|d| d.address == *addr)?;
+        return on_call_profile(&device).then_some(false);
+    }
+
+    if let Some(entries) = pipewire_snapshot() {
+        return pipewire_sco_active(&entries, addr);
+    }
+
+    let device = get_pulseaudio_device(addr)?;
+    on_call_profile(&device).then(|| pulseaudio_device_running(addr))
+}
+
+fn on_call_profile(device: &AudioDevice) -> bool {
+    device
+        .active_profile()
+        .is_some_and(|p| p.category == ProfileCategory::Duplex)
+}
+
+fn pipewire_sco_active(entries: &[PwDumpEntry], addr: &Address) -> Option<bool> {
+    let device = parse_pipewire_device(entries, addr)?;
+    let AudioDeviceId::Pipewire(id) = device.id else {
+        return None;
+    };
+    on_call_profile(&device).then(|| pipewire_device_running(entries, id))
+}
 
-        return Some(AudioDevice {
-            id: AudioDeviceId::Pulseaudio(card.name.clone()),
-            profiles,
-            active_profile_index,
+/// Whether PipeWire suspended the device's nodes for being idle. A suspended device
+/// can look like it has no format until something plays. `None` when the nodes
+/// report no state, on PulseAudio, and for fake devices.
+pub fn is_node_suspended(addr: &Address) -> Option<bool> {
+    if mock::is_active() {
+        return None;
+    }
+
+    let entries = pipewire_snapshot()?;
+    let AudioDeviceId::Pipewire(id) = parse_pipewire_device(&entries, addr)?.id else {
+        return None;
+    };
+    pipewire_device_suspended(&entries, id)
+}
+
+/// The backend's own JSON for the objects of the device, pretty-printed: the
+/// `pw-dump` device and nodes, or the `pactl` card. `None` without a backend.
+pub fn raw_backend_json(addr: &Address) -> Option<String> {
+    let (_, objects, pointers) = backend_objects()?;
+    let matching: Vec<&serde_json::Value> = matching_objects(&objects, pointers, addr)
+        .into_iter()
+        .map(|(object, _, _)| object)
+        .collect();
+
+    serde_json::to_string_pretty(&matching).ok()
+}
+
+/// Which backend objects belong to the device and the property that tied each one
+/// to it, one line per object, e.g.
+/// `pw-dump object 40 (PipeWire:Interface:Device): api.bluez5.address = AA:BB:CC:DD:EE:FF`.
+/// `None` without a backend.
+pub fn explain_match(addr: &Address) -> Option<String> {
+    if let Some(devices) = mock::devices() {
+        return Some(match devices.iter().find(|d| d.address == *addr) {
+            Some(device) => format!("mock device {:?}: address = {addr}", device.id),
+            None => "no mock device has this address".to_string(),
         });
     }
 
-    None
+    let (command, objects, pointers) = backend_objects()?;
+    Some(explain_matches(
+        command,
+        &matching_objects(&objects, pointers, addr),
+    ))
 }
 
-#[derive(Deserialize)]
-struct PaCard {
-    #[serde(default)]
-    name: String,
-    #[serde(default)]
-    properties: std::collections::HashMap<String, String>,
-    #[serde(default)]
-    profiles: Vec<PaProfile>,
-    #[serde(rename = "active_profile", default)]
-    active_profile: Option<String>,
+/// Every object the backend lists, with the properties that may carry an address.
+fn backend_objects() -> Option<(
+    &'static str,
+    Vec<serde_json::Value>,
+    &'static [&'static str],
+)> {
+    let (command, output, pointers): (_, _, &[&str]) = match detect_backend()? {
+        Backend::Pipewire => (
+            "pw-dump",
+            run("pw-dump", &[]),
+            &["/info/props/api.bluez5.address"],
+        ),
+        Backend::Pulseaudio => (
+            "pactl",
+            run("pactl", &["--format=json", "list", "cards"]),
+            &[
+                "/properties/api.bluez5.address",
+                "/properties/device.string",
+            ],
+        ),
+        Backend::Mock => return None,
+    };
+    let output = output.ok().filter(|o| o.status.success())?;
+    let objects = serde_json::from_slice(&output.stdout).ok()?;
+    Some((command, objects, pointers))
 }
 
-#[derive(Deserialize)]
-struct PaProfile {
-    #[serde(default)]
-    name: String,
-    #[serde(default)]
-    description: String,
-    #[serde(default)]
-    available: bool,
+/// The objects one of whose `pointers` holds `addr`, with that pointer and its value.
+fn matching_objects<'a>(
+    objects: &'a [serde_json::Value],
+    pointers: &[&'static str],
+    addr: &Address,
+) -> Vec<(&'a serde_json::Value, &'static str, &'a str)> {
+    objects
+        .iter()
+        .filter_map(|object| {
+            pointers.iter().find_map(|&pointer| {
+                let value = object.pointer(pointer)?.as_str()?;
+                (address_from_bluez_format(value) == Some(*addr))
+                    .then_some((object, pointer, value))
+            })
+        })
+        .collect()
 }
 
-fn switch_pulseaudio_profile(card_name: &str, profile_name: &str) -> Result<String, String> {
-    let output = Command::new("pactl")
-        .args(["set-card-profile", card_name, profile_name])
-        .output()
-        .map_err(|e| format!("Failed to run pactl: {e}"))?;
+fn explain_matches(command: &str, matches: &[(&serde_json::Value, &'static str, &str)]) -> String {
+    if matches.is_empty() {
+        return format!("no {command} object has this address");
+    }
 
-    if output.status.success() {
-        Ok("Profile switched".to_string())
-    } else {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        Err(format!("pactl failed: {stderr}"))
+    matches
+        .iter()
+        .map(|(object, pointer, value)| {
+            // PipeWire objects are known by id and type, PulseAudio cards by name
+            let label = match (object.get("id"), object.get("type"), object.get("name")) {
+                (Some(id), Some(kind), _) => {
+                    format!("object {id} ({})", kind.as_str().unwrap_or("?"))
+                }
+                (Some(id), None, _) => format!("object {id}"),
+                (None, _, Some(name)) => format!("card {}", name.as_str().unwrap_or("?")),
+                (None, _, None) => "object".to_string(),
+            };
+            let property = pointer.rsplit('/').next().unwrap_or(pointer);
+            format!("{command} {label}: {property} = {value}")
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Whether the backend's bluetooth support is loaded: `module-bluez5-discover` for
+/// PulseAudio. PipeWire loads it inside WirePlumber where it can not be listed, so a
+/// bluez5 device or node showing up is taken as the sign.
+pub fn bluez5_loaded() -> bool {
+    match detect_backend() {
+        Some(Backend::Pulseaudio) => run("pactl", &["list", "short", "modules"])
+            .is_ok_and(|o| String::from_utf8_lossy(&o.stdout).contains("module-bluez5-discover")),
+        Some(Backend::Pipewire) => pipewire_snapshot().is_some_and(|entries| {
+            entries
+                .iter()
+                .any(|e| e.props().is_some_and(|p| p.bluez5_address.is_some()))
+        }),
+        Some(Backend::Mock) => true,
+        None => false,
+    }
+}
+
+/// Sample rates the device's sink offers, ascending. Empty when the backend does
+/// not say.
+pub fn supported_sample_rates(addr: &Address) -> Vec<u32> {
+    if mock::is_active() {
+        return Vec::new();
+    }
+
+    if let Some(entries) = pipewire_snapshot() {
+        let Some(AudioDeviceId::Pipewire(id)) = parse_pipewire_device(&entries, addr).map(|d| d.id)
+        else {
+            return Vec::new();
+        };
+        return pipewire_sink_node(&entries, id)
+            .map(pipewire_sample_rates)
+            .unwrap_or_default();
+    }
+
+    // PulseAudio only tells the rate in use
+    pulseaudio_sink(&pulseaudio_sinks(), addr)
+        .and_then(|sink| {
+            sink.sample_specification
+                .split_whitespace()
+                .find_map(|part| part.strip_suffix("Hz")?.parse().ok())
+        })
+        .into_iter()
+        .collect()
+}
+
+/// Audio profiles the device advertises over bluetooth, highest connect priority
+/// first. Works while the device is disconnected.
+pub fn advertised_profiles(addr: &Address) -> Vec<ProfileKind> {
+    let mut kinds: Vec<ProfileKind> = bluez::info(addr)
+        .map(|info| info.uuids)
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|uuid| ProfileKind::from_uuid(uuid))
+        .collect();
+
+    kinds.sort_by_key(|k| std::cmp::Reverse(k.connect_priority()));
+    kinds.dedup();
+    kinds
+}
+
+/// The kind of profile the device will most likely come up in when it connects: the
+/// one last picked by the user if still advertised, else the highest priority one.
+/// A guess for the UI, the backend decides.
+pub fn predicted_connect_profile(addr: &Address) -> Option<ProfileKind> {
+    let preferred = crate::preferences::preferred_profile(addr)
+        .map(|name| ProfileKind::from_profile_name(&name));
+    predict_connect_profile(&advertised_profiles(addr), preferred)
+}
+
+fn predict_connect_profile(
+    advertised: &[ProfileKind],
+    preferred: Option<ProfileKind>,
+) -> Option<ProfileKind> {
+    preferred
+        .filter(|kind| advertised.contains(kind))
+        .or_else(|| advertised.first().copied())
+}
+
+/// Bluetooth device the default output sink belongs to, `None` when the default sink is
+/// not a Bluetooth one.
+pub fn current_default_device() -> Option<Address> {
+    if let Some(default) = mock::default_output() {
+        return default;
+    }
+
+    match pipewire_snapshot() {
+        Some(entries) => pipewire_default_device(&entries),
+        None => pulseaudio_default_device(),
+    }
+}
+
+/// What [`refresh_all_bounded`] found before its deadline.
+#[derive(Debug, Clone, Default)]
+pub struct RefreshReport {
+    pub devices: Vec<AudioDevice>,
+    /// Answered, but the backend has no card for them
+    pub missing: Vec<Address>,
+    /// Still unknown when the deadline passed, keep showing what was known before
+    pub stale: Vec<Address>,
+}
+
+/// Look every device up in parallel, giving up on those that take longer than
+/// `deadline` instead of holding the others back.
+pub fn refresh_all_bounded(devices: &[Address], deadline: Duration) -> RefreshReport {
+    refresh_bounded_with(devices, deadline, get_audio_device)
+}
+
+/// [`refresh_all_bounded`] looking each device up with `lookup`.
+fn refresh_bounded_with(
+    devices: &[Address],
+    deadline: Duration,
+    lookup: fn(&Address) -> Option<AudioDevice>,
+) -> RefreshReport {
+    let (tx, rx) = mpsc::channel();
+    for &addr in devices {
+        let tx = tx.clone();
+        // Late lookups finish in the background, their result is dropped
+        thread::spawn(move || {
+            let _ = tx.send((addr, lookup(&addr)));
+        });
+    }
+    drop(tx);
+
+    let mut report = RefreshReport::default();
+    let mut pending: Vec<Address> = devices.to_vec();
+    let end = Instant::now() + deadline;
+
+    while !pending.is_empty() {
+        let Ok((addr, device)) = rx.recv_timeout(end.saturating_duration_since(Instant::now()))
+        else {
+            break;
+        };
+        pending.retain(|a| *a != addr);
+        match device {
+            Some(device) => report.devices.push(device),
+            None => report.missing.push(addr),
+        }
+    }
+
+    report.stale = pending;
+    report
+}
+
+/// [`all_audio_devices`] together with [`current_default_device`], read from a single
+/// backend snapshot.
+pub fn audio_devices_with_default() -> (Vec<AudioDevice>, Option<Address>) {
+    if let (Some(devices), Some(default)) = (mock::devices(), mock::default_output()) {
+        return (devices, default);
+    }
+
+    if let Some(entries) = pipewire_snapshot() {
+        return (
+            parse_pipewire_devices(&entries),
+            pipewire_default_device(&entries),
+        );
+    }
+
+    (all_audio_devices(), pulseaudio_default_device())
+}
+
+/// Switch profile using whichever backend owns the device.
+///
+/// `address` and `previous` only go into the event log; pass what is known.
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(skip(device), fields(device = ?device))
+)]
+pub fn switch_profile(
+    device: &AudioDeviceId,
+    profile_index: u32,
+    profile_name: &str,
+    address: Option<Address>,
+    previous: Option<&str>,
+) -> Result<String, ProfileError> {
+    let command = match device {
+        AudioDeviceId::Pipewire(_) => "wpctl",
+        AudioDeviceId::Pulseaudio(_) => "pactl",
+    };
+
+    let result = switch_profile_command(device, profile_index, profile_name)
+        .and_then(|output| output.check(command));
+    event_log::record(
+        device
+            .event("profile", address, profile_name)
+            .old(previous)
+            .outcome(&result),
+    );
+
+    result?;
+    Ok("Profile switched".to_string())
+}
+
+/// Like [`switch_profile`], but hands back the backend command's exit code and output
+/// instead of interpreting them. `Err` only when the command could not be run at all.
+pub fn switch_profile_raw(
+    device: &AudioDeviceId,
+    profile_index: u32,
+    profile_name: &str,
+    address: Option<Address>,
+    previous: Option<&str>,
+) -> Result<BackendResult, ProfileError> {
+    let result = switch_profile_command(device, profile_index, profile_name);
+    let outcome = match &result {
+        Ok(output) if !output.success() => Err(output.stderr.trim().to_string()),
+        Ok(_) => Ok(()),
+        Err(e) => Err(e.to_string()),
+    };
+    event_log::record(
+        device
+            .event("profile", address, profile_name)
+            .old(previous)
+            .outcome(&outcome),
+    );
+    result
+}
+
+/// Whether the device is pinned to its profile, see [`crate::preferences::set_locked`].
+pub fn is_locked(addr: &Address) -> bool {
+    mock::is_locked(addr).unwrap_or_else(|| crate::preferences::is_locked(addr))
+}
+
+/// Pin the device to its profile, or release it.
+pub fn set_locked(addr: &Address, locked: bool) -> AppResult<()> {
+    match mock::set_locked(addr, locked) {
+        Some(()) => Ok(()),
+        None => crate::preferences::set_locked(addr, locked),
+    }
+}
+
+/// The profile lock check every switch makes: a locked device refuses any profile
+/// or codec change that is not `force`d.
+fn ensure_unlocked(addr: &Address, force: bool) -> Result<(), ProfileError> {
+    if !force && is_locked(addr) {
+        return Err(ProfileError::Locked(*addr));
+    }
+    Ok(())
+}
+
+/// [`switch_profile`] that respects the device's profile lock: locked devices are
+/// left alone unless `force` is set.
+pub fn switch_device_profile(
+    device: &AudioDevice,
+    profile: &AudioProfile,
+    force: bool,
+) -> Result<String, ProfileError> {
+    ensure_unlocked(&device.address, force)?;
+
+    switch_profile(
+        &device.id,
+        profile.index,
+        &profile.name,
+        Some(device.address),
+        device.active_profile_name(),
+    )
+}
+
+/// Switch the device off and back to its active profile, making the backend set the
+/// transport up again. Does nothing when no profile is active.
+///
+/// Switching back is tried twice; if both fail the device stays off and
+/// [`ProfileError::LeftOff`] says so. Locked devices are left alone.
+pub fn reapply_active_profile(device: &AudioDevice) -> Result<String, ProfileError> {
+    let Some(active) = device.active_profile() else {
+        return Ok("No active profile to re-apply".to_string());
+    };
+    ensure_unlocked(&device.address, false)?;
+
+    let off = off_profile_index(device)?;
+    reapply_with(active, off, |index, name, previous| {
+        switch_profile(
+            &device.id,
+            index,
+            name,
+            Some(device.address),
+            Some(previous),
+        )
+    })
+}
+
+/// The off → `active` round trip of [`reapply_active_profile`], switching through
+/// `switch(index, name, previous)`.
+fn reapply_with(
+    active: &AudioProfile,
+    off_index: u32,
+    mut switch: impl FnMut(u32, &str, &str) -> Result<String, ProfileError>,
+) -> Result<String, ProfileError> {
+    switch(off_index, "off", &active.name)?;
+
+    switch(active.index, &active.name, "off")
+        .or_else(|_| switch(active.index, &active.name, "off"))
+        .map(|_| format!("Re-applied {}", active.description))
+        .map_err(|e| ProfileError::LeftOff {
+            profile: active.name.clone(),
+            reason: e.to_string(),
+        })
+}
+
+/// Index of the device's `off` profile, which [`AudioDevice::profiles`] leaves out.
+fn off_profile_index(device: &AudioDevice) -> Result<u32, ProfileError> {
+    match &device.id {
+        // Switched by name, the index is not used
+        AudioDeviceId::Pulseaudio(_) => Ok(0),
+        AudioDeviceId::Pipewire(_) if mock::is_active() => Ok(mock::OFF_PROFILE_INDEX),
+        AudioDeviceId::Pipewire(id) => {
+            let entries =
+                pipewire_snapshot().ok_or(ProfileError::BackendNotInstalled("pw-dump"))?;
+            pipewire_off_profile_index(&entries, *id)
+                .ok_or_else(|| ProfileError::ProfileUnavailable("off".to_string()))
+        }
+    }
+}
+
+fn pipewire_off_profile_index(entries: &[PwDumpEntry], device_id: u32) -> Option<u32> {
+    entries
+        .iter()
+        .find(|e| e.id == device_id)?
+        .params()?
+        .enum_profile
+        .iter()
+        .find(|p| p.name.as_deref() == Some("off"))
+        .map(|p| p.index)
+}
+
+fn switch_profile_command(
+    device: &AudioDeviceId,
+    profile_index: u32,
+    profile_name: &str,
+) -> Result<BackendResult, ProfileError> {
+    if let Some(result) = mock::switch_profile(device, profile_index) {
+        return Ok(result);
+    }
+
+    match device {
+        AudioDeviceId::Pipewire(id) => switch_pipewire_profile(*id, profile_index),
+        AudioDeviceId::Pulseaudio(card) => switch_pulseaudio_profile(card, profile_name),
+    }
+}
+
+/// Make the device's sink the default output.
+pub fn set_default_output(addr: &Address) -> Result<(), ProfileError> {
+    if let Some(known) = mock::set_default_output(addr) {
+        return known
+            .then_some(())
+            .ok_or(ProfileError::NoAudioDevice(*addr));
+    }
+
+    if let Some(entries) = pipewire_snapshot() {
+        let sink = parse_pipewire_device(&entries, addr)
+            .and_then(|device| match device.id {
+                AudioDeviceId::Pipewire(id) => pipewire_sink_node(&entries, id),
+                AudioDeviceId::Pulseaudio(_) => None,
+            })
+            .ok_or(ProfileError::NoAudioDevice(*addr))?;
+
+        return run_backend("wpctl", &["set-default", &sink.id.to_string()]);
+    }
+
+    let sinks = pulseaudio_sinks();
+    let sink = pulseaudio_sink(&sinks, addr).ok_or(ProfileError::NoAudioDevice(*addr))?;
+    run_backend("pactl", &["set-default-sink", &sink.name])
+}
+
+// ── volume ─────────────────────────────────────────────────────────
+
+/// Whether the device takes AVRCP absolute volume, i.e. its BlueZ media transport
+/// exposes a `Volume` property.
+pub fn hw_volume_supported(addr: &Address) -> bool {
+    bluez::transport(addr).is_some_and(|t| t.volume.is_some())
+}
+
+/// Hardware volume support as each backend sees it; `None` when that backend is not
+/// running or has no sink for the device.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HwVolumeSupport {
+    pub pipewire: Option<bool>,
+    pub pulseaudio: Option<bool>,
+}
+
+/// Ask both backends, independently of which one [`detect_backend`] picks, whether
+/// they drive the device's volume in hardware.
+pub fn hw_volume_support(addr: &Address) -> HwVolumeSupport {
+    if mock::is_active() {
+        return HwVolumeSupport::default();
+    }
+
+    let pipewire = pipewire_snapshot().and_then(|entries| {
+        let device = parse_pipewire_device(&entries, addr)?;
+        let AudioDeviceId::Pipewire(id) = device.id else {
+            return None;
+        };
+        let sink = pipewire_sink_node(&entries, id)?;
+        Some(
+            sink.props()
+                .is_some_and(|p| json_truthy(&p.bluez5_hw_volume)),
+        )
+    });
+
+    let pulseaudio = pulseaudio_sink(&pulseaudio_sinks(), addr)
+        .map(|sink| sink.flags.iter().any(|f| f == "HW_VOLUME_CTRL"));
+
+    HwVolumeSupport {
+        pipewire,
+        pulseaudio,
+    }
+}
+
+/// Current volume in percent, read from the headset when `prefer_hw_volume` is set
+/// and it supports absolute volume.
+pub fn get_volume(addr: &Address, prefer_hw_volume: bool) -> Option<u8> {
+    if prefer_hw_volume && let Some(volume) = bluez::transport(addr).and_then(|t| t.volume) {
+        return Some(bluez::transport_volume_percent(volume));
+    }
+
+    get_audio_device(addr)?.volume
+}
+
+/// Set the output volume.
+///
+/// With `prefer_hw_volume` the change goes straight to the headset over AVRCP when
+/// supported; otherwise, or as a fallback, the backend's software volume is used.
+pub fn set_volume(
+    addr: &Address,
+    percent: u8,
+    prefer_hw_volume: bool,
+) -> Result<String, ProfileError> {
+    apply_volume(addr, None, percent, prefer_hw_volume)
+}
+
+/// Move the volume by `delta` percent points.
+pub fn change_volume(
+    addr: &Address,
+    delta: i8,
+    prefer_hw_volume: bool,
+) -> Result<String, ProfileError> {
+    let current = get_volume(addr, prefer_hw_volume).ok_or(ProfileError::NoAudioDevice(*addr))?;
+    let target = (i16::from(current) + i16::from(delta)).clamp(0, 100) as u8;
+    apply_volume(addr, Some(current), target, prefer_hw_volume)
+}
+
+fn apply_volume(
+    addr: &Address,
+    old: Option<u8>,
+    percent: u8,
+    prefer_hw_volume: bool,
+) -> Result<String, ProfileError> {
+    let percent = percent.min(100);
+
+    if let Some(transport) = hardware_volume_transport(prefer_hw_volume, || bluez::transport(addr))
+    {
+        let result = bluez::set_transport_volume(&transport.path, percent);
+        event_log::record(volume_event(addr, "avrcp", old, percent).outcome(&result));
+        result?;
+        return Ok(format!("Volume {percent}% (hardware)"));
+    }
+
+    let result = set_software_volume(addr, percent);
+    let backend = match detect_backend() {
+        Some(Backend::Pulseaudio) => "pulseaudio",
+        Some(Backend::Mock) => "mock",
+        _ => "pipewire",
+    };
+    event_log::record(volume_event(addr, backend, old, percent).outcome(&result));
+    result?;
+    Ok(format!("Volume {percent}%"))
+}
+
+/// The transport to set the volume on in hardware, `None` to go through the backend
+/// because hardware volume is not wanted or the headset does not support it.
+fn hardware_volume_transport(
+    prefer_hw_volume: bool,
+    lookup: impl FnOnce() -> Option<bluez::Transport>,
+) -> Option<bluez::Transport> {
+    if !prefer_hw_volume {
+        return None;
+    }
+    lookup().filter(|t| t.volume.is_some())
+}
+
+fn volume_event(addr: &Address, backend: &'static str, old: Option<u8>, new: u8) -> Event {
+    Event::new("volume", backend, new.to_string())
+        .address(addr)
+        .old(old)
+}
+
+/// What [`set_absolute_volume`] changed and what it takes to apply it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AbsoluteVolumeChange {
+    pub enabled: bool,
+    /// WirePlumber only reads its rules when it starts
+    pub requires_wireplumber_restart: bool,
+    /// The device keeps the old setting until it reconnects
+    pub requires_reconnect: bool,
+}
+
+impl fmt::Display for AbsoluteVolumeChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = if self.enabled { "on" } else { "off" };
+        write!(f, "Absolute volume {state}")?;
+        match (self.requires_wireplumber_restart, self.requires_reconnect) {
+            (true, true) => write!(
+                f,
+                ", restart WirePlumber (systemctl --user restart wireplumber) and reconnect the device to apply"
+            ),
+            (true, false) => write!(
+                f,
+                ", restart WirePlumber (systemctl --user restart wireplumber) to apply"
+            ),
+            (false, true) => write!(f, ", reconnect the device to apply"),
+            (false, false) => Ok(()),
+        }
+    }
+}
+
+/// Turn AVRCP absolute volume on or off.
+///
+/// With PipeWire this drops a WirePlumber rule for the device (or removes it to go
+/// back to the default), which WirePlumber picks up on its next start, and the
+/// device has to reconnect. PulseAudio only has a global switch on its bluetooth
+/// module, so it is refused with [`ProfileError::RestartRequired`] rather than
+/// dropping every bluetooth audio device to change one.
+pub fn set_absolute_volume(
+    device: &AudioDeviceId,
+    enabled: bool,
+) -> Result<AbsoluteVolumeChange, ProfileError> {
+    if mock::is_active() {
+        return Ok(AbsoluteVolumeChange {
+            enabled,
+            requires_wireplumber_restart: false,
+            requires_reconnect: false,
+        });
+    }
+
+    match device {
+        AudioDeviceId::Pipewire(id) => {
+            let entries =
+                pipewire_snapshot().ok_or(ProfileError::BackendNotInstalled("pw-dump"))?;
+            let name = entries
+                .iter()
+                .find(|e| e.id == *id && e.kind == PW_TYPE_DEVICE)
+                .and_then(|e| e.props()?.device_name.clone())
+                .ok_or(ProfileError::DeviceNotFound(*id))?;
+            write_hw_volume_rule(&name, enabled)?;
+
+            Ok(AbsoluteVolumeChange {
+                enabled,
+                requires_wireplumber_restart: true,
+                requires_reconnect: true,
+            })
+        }
+        AudioDeviceId::Pulseaudio(_) => Err(ProfileError::RestartRequired("absolute volume")),
+    }
+}
+
+/// Write (or remove, when enabling) the WirePlumber rule clearing `bluez5.hw-volume`
+/// for the device named `device_name`.
+fn write_hw_volume_rule(device_name: &str, enabled: bool) -> Result<(), ProfileError> {
+    let io_error = |e: io::Error| ProfileError::Io {
+        command: "wireplumber",
+        message: e.to_string(),
+    };
+
+    let dir = dirs::config_dir()
+        .ok_or_else(|| io_error(io::Error::other("unable to find config_dir")))?
+        .join("wireplumber")
+        .join("wireplumber.conf.d");
+    let path = dir.join(format!("51-bluetui-hw-volume-{device_name}.conf"));
+
+    if enabled {
+        return match std::fs::remove_file(&path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(io_error(e)),
+            _ => Ok(()),
+        };
+    }
+
+    std::fs::create_dir_all(&dir).map_err(io_error)?;
+    std::fs::write(path, hw_volume_rule(device_name)).map_err(io_error)
+}
+
+fn hw_volume_rule(device_name: &str) -> String {
+    format!(
+        r#"# Written by bluetui: no AVRCP absolute volume for this device
+monitor.bluez.rules = [
+  {{
+    matches = [ {{ device.name = "{device_name}" }} ]
+    actions = {{ update-props = {{ bluez5.hw-volume = [ ] }} }}
+  }}
+]
+"#
+    )
+}
+
+fn set_software_volume(addr: &Address, percent: u8) -> Result<(), ProfileError> {
+    if let Some(known) = mock::set_volume(addr, percent) {
+        return known
+            .then_some(())
+            .ok_or(ProfileError::NoAudioDevice(*addr));
+    }
+
+    if let Some(entries) = pipewire_snapshot() {
+        let sink = parse_pipewire_device(&entries, addr)
+            .and_then(|device| match device.id {
+                AudioDeviceId::Pipewire(id) => pipewire_sink_node(&entries, id),
+                AudioDeviceId::Pulseaudio(_) => None,
+            })
+            .ok_or(ProfileError::NoAudioDevice(*addr))?;
+
+        let volume = format!("{:.2}", f32::from(percent) / 100.0);
+        return run_backend("wpctl", &["set-volume", &sink.id.to_string(), &volume]);
+    }
+
+    let sinks = pulseaudio_sinks();
+    let sink = pulseaudio_sink(&sinks, addr).ok_or(ProfileError::NoAudioDevice(*addr))?;
+    run_backend(
+        "pactl",
+        &["set-sink-volume", &sink.name, &format!("{percent}%")],
+    )
+}
+
+// ── codecs ─────────────────────────────────────────────────────────
+
+/// Profiles PipeWire duplicates once per codec, as `<base>-<codec>`.
+const CODEC_PROFILE_BASES: [&str; 3] = ["a2dp-sink", "a2dp-duplex", "headset-head-unit"];
+
+/// Pair each profile with the codecs it can run, for a profile → codec picker.
+///
+/// Codec specific variants (`a2dp-sink-aac`, `a2dp-sink-ldac`) are folded under their
+/// base profile; when the base profile itself is not listed the first variant stands
+/// in for it. Profiles without codec choices get an empty list.
+pub fn profile_codec_matrix(addr: &Address) -> Vec<(AudioProfile, Vec<String>)> {
+    get_audio_device(addr)
+        .map(|device| codec_matrix(&device.profiles))
+        .unwrap_or_default()
+}
+
+fn codec_matrix(profiles: &[AudioProfile]) -> Vec<(AudioProfile, Vec<String>)> {
+    let mut matrix: Vec<(String, AudioProfile, Vec<String>)> = Vec::new();
+
+    for profile in profiles {
+        let base = profile.base_name();
+        let codec = profile.codec();
+        let is_variant = profile.split_codec_suffix().1.is_some();
+
+        match matrix.iter_mut().find(|(b, _, _)| *b == base) {
+            Some((_, entry, codecs)) => {
+                if !is_variant {
+                    *entry = profile.clone();
+                }
+                if let Some(codec) = codec
+                    && is_variant
+                    && !codecs.contains(&codec)
+                {
+                    codecs.push(codec);
+                }
+            }
+            None => {
+                let codecs = codec.filter(|_| is_variant).into_iter().collect();
+                matrix.push((base, profile.clone(), codecs));
+            }
+        }
+    }
+
+    matrix
+        .into_iter()
+        .map(|(_, profile, codecs)| (profile, codecs))
+        .collect()
+}
+
+/// Human readable codec name, `aptx_hd` → `aptX HD`.
+pub fn codec_label(codec: &str) -> String {
+    match codec {
+        "sbc" => "SBC".to_string(),
+        "sbc_xq" => "SBC-XQ".to_string(),
+        "aac" => "AAC".to_string(),
+        "aptx" => "aptX".to_string(),
+        "aptx_hd" => "aptX HD".to_string(),
+        "aptx_ll" => "aptX LL".to_string(),
+        "aptx_ll_duplex" => "aptX LL Duplex".to_string(),
+        "ldac" => "LDAC".to_string(),
+        "lc3" => "LC3".to_string(),
+        "msbc" => "mSBC".to_string(),
+        "cvsd" => "CVSD".to_string(),
+        "faststream" => "FastStream".to_string(),
+        other => other.to_uppercase(),
+    }
+}
+
+/// Codecs from best to worst sounding, roughly by bitrate.
+const CODEC_RANKING: [&str; 11] = [
+    "ldac",
+    "aptx_hd",
+    "lc3",
+    "aptx",
+    "aac",
+    "sbc_xq",
+    "aptx_ll",
+    "faststream",
+    "sbc",
+    "msbc",
+    "cvsd",
+];
+
+/// Whether `codec` is one of the names the backends use, e.g. `aptx_hd`.
+pub fn is_known_codec(codec: &str) -> bool {
+    CODEC_RANKING.contains(&codec)
+}
+
+/// Codecs only used for calls (HFP), not listed in `bluez5.codecs`.
+const CALL_CODECS: [&str; 2] = ["msbc", "cvsd"];
+
+/// Where WirePlumber reads its config fragments, lowest precedence first. The lua
+/// directory is where WirePlumber before 0.5 kept its bluetooth settings.
+fn wireplumber_config_dirs() -> Vec<std::path::PathBuf> {
+    let mut dirs: Vec<std::path::PathBuf> = ["/usr/share/wireplumber", "/etc/wireplumber"]
+        .into_iter()
+        .map(Into::into)
+        .collect();
+    dirs.extend(dirs::config_dir().map(|dir| dir.join("wireplumber")));
+
+    dirs.into_iter()
+        .flat_map(|dir| [dir.join("bluetooth.lua.d"), dir.join("wireplumber.conf.d")])
+        .collect()
+}
+
+/// Every A2DP codec with whether the PipeWire config allows it, from the
+/// `bluez5.codecs` setting of the WirePlumber config. All are enabled when no
+/// config restricts them. This is what may be negotiated, not what is.
+pub fn codec_config_state() -> Vec<(String, bool)> {
+    let mut enabled: Option<Vec<String>> = None;
+    for dir in wireplumber_config_dirs() {
+        let Ok(files) = std::fs::read_dir(dir) else {
+            continue;
+        };
+        let mut files: Vec<_> = files.filter_map(|f| Some(f.ok()?.path())).collect();
+        files.sort();
+
+        for file in files {
+            if let Some(codecs) = std::fs::read_to_string(file)
+                .ok()
+                .and_then(|text| parse_codecs_setting(&text))
+            {
+                enabled = Some(codecs);
+            }
+        }
+    }
+
+    codec_states(enabled.as_deref())
+}
+
+/// Every A2DP codec with whether `enabled` lists it, all of them without a list.
+fn codec_states(enabled: Option<&[String]>) -> Vec<(String, bool)> {
+    CODEC_RANKING
+        .iter()
+        .filter(|codec| !CALL_CODECS.contains(codec))
+        .map(|&codec| {
+            let on = enabled.is_none_or(|list| list.iter().any(|c| c == codec));
+            (codec.to_string(), on)
+        })
+        .collect()
+}
+
+/// The last global `bluez5.codecs` list of a config file, either
+/// `bluez5.codecs = [ sbc sbc_xq aac ]` or the lua `["bluez5.codecs"] = "[ sbc aac ]"`.
+/// Lists inside `monitor.bluez.rules` (`bluez_monitor.rules` in lua) only apply to
+/// the devices they match and are skipped.
+fn parse_codecs_setting(text: &str) -> Option<Vec<String>> {
+    let text: String = text
+        .lines()
+        .map(|line| {
+            let line = line.split('#').next().unwrap_or_default();
+            line.split("--").next().unwrap_or_default()
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    let text = strip_device_rules(&text);
+
+    let (_, after) = text.rsplit_once("bluez5.codecs")?;
+    let (_, list) = after.split_once('[')?;
+    let (list, _) = list.split_once(']')?;
+
+    Some(
+        list.split(|c: char| c.is_whitespace() || c == ',' || c == '"')
+            .filter(|codec| !codec.is_empty())
+            .map(str::to_string)
+            .collect(),
+    )
+}
+
+/// `text` without the value of its `monitor.bluez.rules` and `bluez_monitor.rules`
+/// settings, the bracketed block after the key. Brackets inside strings are not
+/// counted.
+fn strip_device_rules(text: &str) -> String {
+    let mut rest = text;
+    let mut kept = String::new();
+
+    while let Some(start) = ["monitor.bluez.rules", "bluez_monitor.rules"]
+        .iter()
+        .filter_map(|key| rest.find(key))
+        .min()
+    {
+        kept.push_str(&rest[..start]);
+        let Some(open) = rest[start..].find(['[', '{']) else {
+            return kept;
+        };
+
+        let mut depth = 0usize;
+        let mut in_string = false;
+        let mut end = rest.len();
+        for (i, c) in rest[start + open..].char_indices() {
+            match c {
+                '"' => in_string = !in_string,
+                '[' | '{' if !in_string => depth += 1,
+                ']' | '}' if !in_string => {
+                    depth -= 1;
+                    if depth == 0 {
+                        end = start + open + i + 1;
+                        break;
+                    }
+                }
+                _ => {}
+            }
+        }
+        rest = &rest[end..];
+    }
+
+    kept.push_str(rest);
+    kept
+}
+
+/// The codec to use out of `available`: the first one of `preference` on offer, or
+/// the best sounding one when the preference is empty or none of it is available.
+pub fn preferred_codec(available: &[String], preference: &[String]) -> Option<String> {
+    preference
+        .iter()
+        .find(|codec| available.contains(codec))
+        .or_else(|| {
+            available.iter().reduce(|best, codec| {
+                if codec_is_better(codec, best) {
+                    codec
+                } else {
+                    best
+                }
+            })
+        })
+        .cloned()
+}
+
+/// Switch to the codec [`preferred_codec`] picks, unless it is already in use.
+pub fn switch_preferred_codec(
+    device: &AudioDevice,
+    preference: &[String],
+) -> Result<Option<String>, ProfileError> {
+    match preferred_codec(&available_codecs(device), preference) {
+        Some(codec) if device.codec.as_deref() != Some(codec.as_str()) => {
+            switch_codec(device, &codec, false)?;
+            Ok(Some(codec))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Whether `a` sounds better than `b`; unknown codecs rank below known ones.
+pub fn codec_is_better(a: &str, b: &str) -> bool {
+    let rank = |codec: &str| {
+        CODEC_RANKING
+            .iter()
+            .position(|c| *c == codec)
+            .unwrap_or(CODEC_RANKING.len())
+    };
+    rank(a) < rank(b)
+}
+
+/// Codecs selectable within the active profile, or within A2DP when no profile is
+/// active.
+pub fn available_codecs(device: &AudioDevice) -> Vec<String> {
+    let base = device.active_profile().map(AudioProfile::base_name);
+
+    codec_matrix(&device.profiles)
+        .into_iter()
+        .find(|(profile, _)| match &base {
+            Some(base) => profile.base_name() == *base,
+            None => profile.kind() == ProfileKind::A2dp,
+        })
+        .map(|(_, codecs)| codecs)
+        .unwrap_or_default()
+}
+
+/// Switch to the variant of the active profile (A2DP when none is active) that pins
+/// `codec`. Locked devices are left alone unless `force` is set.
+pub fn switch_codec(
+    device: &AudioDevice,
+    codec: &str,
+    force: bool,
+) -> Result<String, ProfileError> {
+    ensure_unlocked(&device.address, force)?;
+    let base = device.active_profile().map(AudioProfile::base_name);
+
+    let target = device
+        .profiles
+        .iter()
+        .filter(|p| match &base {
+            Some(base) => p.base_name() == *base,
+            None => p.kind() == ProfileKind::A2dp,
+        })
+        .find(|p| p.split_codec_suffix().1.is_some() && p.codec().as_deref() == Some(codec))
+        .ok_or_else(|| ProfileError::CodecUnavailable(codec.to_string()))?;
+
+    let command = match device.id {
+        AudioDeviceId::Pipewire(_) => "wpctl",
+        AudioDeviceId::Pulseaudio(_) => "pactl",
+    };
+    let result = switch_profile_command(&device.id, target.index, &target.name)
+        .and_then(|output| output.check(command));
+    event_log::record(
+        Event::new("codec", device.id.backend_name(), codec)
+            .address(device.address)
+            .old(device.codec.as_ref())
+            .outcome(&result),
+    );
+
+    result?;
+    Ok(format!("Switched to {}", codec_label(codec)))
+}
+
+const CARD_WAIT_ATTEMPTS: u32 = 10;
+const CARD_WAIT_INTERVAL: Duration = Duration::from_millis(500);
+
+/// The card shows up a moment after the bluetooth link; wait for it to list its
+/// profiles.
+pub async fn wait_for_card(addr: &Address) -> Option<AudioDevice> {
+    for _ in 0..CARD_WAIT_ATTEMPTS {
+        if let Some(device) = get_audio_device(addr).filter(|d| !d.pending) {
+            return Some(device);
+        }
+        tokio::time::sleep(CARD_WAIT_INTERVAL).await;
+    }
+    None
+}
+
+/// Switch the device to the profile the user last picked for it, if it offers it
+/// and is not on it already. Locked devices stay where they are.
+pub fn apply_preferred_profile(device: &AudioDevice) -> Result<(), ProfileError> {
+    let Some(name) = crate::preferences::preferred_profile(&device.address) else {
+        return Ok(());
+    };
+    if device.active_profile_name() == Some(name.as_str()) {
+        return Ok(());
+    }
+
+    let profile = device
+        .profiles
+        .iter()
+        .find(|p| p.name == name)
+        .ok_or(ProfileError::ProfileUnavailable(name))?;
+    switch_device_profile(device, profile, false)?;
+    Ok(())
+}
+
+/// Connect the device, then move it to the best sounding codec it offers.
+///
+/// Returns the codec the backend actually settled on, which can differ from the one
+/// asked for when negotiation falls back. A locked device is connected but keeps
+/// its codec.
+pub async fn connect_best_codec(addr: &Address) -> Result<String, ProfileError> {
+    connect_preferred_codec(addr, &[]).await
+}
+
+/// [`connect_best_codec`] that tries the codecs of `preference` first, see
+/// [`preferred_codec`]. An empty preference ranks by quality.
+#[cfg_attr(feature = "tracing", tracing::instrument(fields(addr = %addr)))]
+pub async fn connect_preferred_codec(
+    addr: &Address,
+    preference: &[String],
+) -> Result<String, ProfileError> {
+    let session = bluer::Session::new().await?;
+    let adapter = session.default_adapter().await?;
+    let device = adapter.device(*addr)?;
+    if !device.is_connected().await? {
+        // Goes through bluetooth::connect so the connection lands in the history
+        bluetooth::connect(&device).await?;
+    }
+
+    let audio_device = wait_for_card(addr)
+        .await
+        .ok_or(ProfileError::NoAudioDevice(*addr))?;
+
+    match switch_preferred_codec(&audio_device, preference) {
+        Ok(_) | Err(ProfileError::Locked(_)) => {}
+        Err(e) => return Err(e),
+    }
+
+    get_audio_device(addr)
+        .and_then(|d| d.codec)
+        .ok_or(ProfileError::NoAudioDevice(*addr))
+}
+
+// ── equalizer ──────────────────────────────────────────────────────
+
+/// Gains in dB for the 15 bands of the LADSPA `mbeq` plugin, 50 Hz to 20 kHz.
+const EQ_PRESETS: [(&str, [i8; 15]); 2] = [
+    ("bass-boost", [6, 6, 5, 4, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]),
+    ("vocal", [-2, -2, -1, 0, 1, 3, 4, 4, 3, 2, 1, 0, 0, -1, -1]),
+];
+
+/// Prefix of the sinks the equalizer creates, used to find them again.
+const EQ_SINK_PREFIX: &str = "bluetui_eq";
+
+pub fn eq_presets() -> Vec<&'static str> {
+    EQ_PRESETS.iter().map(|(name, _)| *name).collect()
+}
+
+/// Put an equalizer in front of the device's sink, or take it away with `off`.
+///
+/// Loads `module-ladspa-sink` through `pactl`, which PulseAudio and pipewire-pulse
+/// both understand, so it needs the LADSPA `mbeq` plugin (swh-plugins) installed.
+/// Play to the `bluetui_eq_*` sink it creates to hear it.
+pub fn apply_eq_preset(device: &AudioDeviceId, preset: &str) -> Result<String, ProfileError> {
+    if mock::is_active() {
+        return match preset {
+            "off" => Ok("EQ off".to_string()),
+            _ if eq_presets().contains(&preset) => Ok(format!("EQ {preset} applied")),
+            _ => Err(ProfileError::UnknownPreset(preset.to_string())),
+        };
+    }
+
+    let sink = device_sink_name(device).ok_or_else(|| match device {
+        AudioDeviceId::Pipewire(id) => ProfileError::DeviceNotFound(*id),
+        AudioDeviceId::Pulseaudio(card) => ProfileError::CommandFailed {
+            command: "pactl",
+            stderr: format!("no sink for card {card}"),
+        },
+    })?;
+
+    let args = if preset == "off" {
+        None
+    } else {
+        Some(eq_module_args(&sink, preset).ok_or(ProfileError::UnknownPreset(preset.to_string()))?)
+    };
+
+    // Replace rather than stack equalizers
+    for module in eq_modules(&sink)? {
+        run_backend("pactl", &["unload-module", &module])?;
+    }
+
+    let Some(args) = args else {
+        return Ok("EQ off".to_string());
+    };
+    let mut command = vec!["load-module", "module-ladspa-sink"];
+    command.extend(args.iter().map(String::as_str));
+    run_backend("pactl", &command)?;
+
+    Ok(format!("EQ {preset} applied"))
+}
+
+fn eq_module_args(sink: &str, preset: &str) -> Option<Vec<String>> {
+    let (_, gains) = EQ_PRESETS.iter().find(|(name, _)| *name == preset)?;
+    let control = gains.map(|g| g.to_string()).join(",");
+
+    Some(vec![
+        format!("sink_name={EQ_SINK_PREFIX}_{preset}"),
+        format!("sink_master={sink}"),
+        "plugin=mbeq_1197".to_string(),
+        "label=mbeq".to_string(),
+        format!("control={control}"),
+    ])
+}
+
+/// Ids of the equalizer modules loaded for `sink`.
+fn eq_modules(sink: &str) -> Result<Vec<String>, ProfileError> {
+    let output = run_raw("pactl", &["list", "short", "modules"])?.check("pactl")?;
+    let master = format!("sink_master={sink}");
+
+    // "42\tmodule-ladspa-sink\tsink_name=bluetui_eq_vocal sink_master=... "
+    Ok(output
+        .stdout
+        .lines()
+        .filter(|line| line.contains(EQ_SINK_PREFIX) && line.contains(&master))
+        .filter_map(|line| line.split_whitespace().next())
+        .map(str::to_string)
+        .collect())
+}
+
+/// Name of the device's output sink, as `pactl` knows it.
+fn device_sink_name(device: &AudioDeviceId) -> Option<String> {
+    match device {
+        AudioDeviceId::Pipewire(id) => {
+            let entries = pipewire_snapshot()?;
+            pipewire_sink_node(&entries, *id)?
+                .props()?
+                .node_name
+                .clone()
+        }
+        AudioDeviceId::Pulseaudio(card) => {
+            let addr = address_from_bluez_format(card.strip_prefix("bluez_card.")?)?;
+            pulseaudio_sink(&pulseaudio_sinks(), &addr).map(|sink| sink.name.clone())
+        }
+    }
+}
+
+// ── snapshot ───────────────────────────────────────────────────────
+
+/// Audio setup of every connected device, taken by [`snapshot_all`].
+#[derive(Debug, Clone, Default)]
+pub struct AudioSnapshot {
+    pub devices: Vec<DeviceSnapshot>,
+}
+
+#[derive(Debug, Clone)]
+pub struct DeviceSnapshot {
+    pub address: Address,
+    /// Name of the active profile; the index is not stable across reconnects
+    pub profile: Option<String>,
+    pub codec: Option<String>,
+    pub volume: Option<u8>,
+    pub default_output: bool,
+}
+
+pub fn snapshot_all() -> AudioSnapshot {
+    let default = current_default_device();
+    let devices = all_audio_devices()
+        .into_iter()
+        .map(|device| DeviceSnapshot {
+            address: device.address,
+            profile: device.active_profile_name().map(str::to_string),
+            codec: device.codec,
+            volume: device.volume,
+            default_output: default == Some(device.address),
+        })
+        .collect();
+
+    AudioSnapshot { devices }
+}
+
+/// Put every device of the snapshot back the way it was.
+///
+/// Devices are restored independently, so one failing does not stop the others.
+/// Devices that are no longer connected are skipped and left out of the result;
+/// locked ones fail with [`ProfileError::Locked`] when their profile or codec moved.
+pub fn restore(snapshot: &AudioSnapshot) -> Vec<(Address, Result<(), ProfileError>)> {
+    snapshot
+        .devices
+        .iter()
+        .filter_map(|saved| {
+            let device = get_audio_device(&saved.address)?;
+            Some((saved.address, restore_device(&device, saved)))
+        })
+        .collect()
+}
+
+fn restore_device(device: &AudioDevice, saved: &DeviceSnapshot) -> Result<(), ProfileError> {
+    let active = device.active_profile();
+    let mut refreshed = None;
+
+    if let Some(name) = &saved.profile
+        && active.map(|p| &p.name) != Some(name)
+    {
+        let profile = device
+            .profiles
+            .iter()
+            .find(|p| p.name == *name)
+            .ok_or_else(|| ProfileError::ProfileUnavailable(name.clone()))?;
+        switch_device_profile(device, profile, false)?;
+        // The profile decides which codecs are on offer and which one runs
+        refreshed = get_audio_device(&device.address);
+    }
+
+    if let Some(codec) = &saved.codec {
+        let device = refreshed.as_ref().unwrap_or(device);
+        if device.codec.as_ref() != Some(codec) {
+            switch_codec(device, codec, false)?;
+        }
+    }
+
+    if let Some(volume) = saved.volume {
+        set_volume(&device.address, volume, false)?;
+    }
+
+    if saved.default_output {
+        set_default_output(&device.address)?;
+    }
+
+    Ok(())
+}
+
+// ── status line ────────────────────────────────────────────────────
+
+/// Glyph set used by [`status_line_with`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StatusGlyphs {
+    #[default]
+    Unicode,
+    Ascii,
+}
+
+/// Everything shown in a status line; missing fields are left out.
+#[derive(Debug, Clone, Default)]
+pub struct StatusLine {
+    pub name: Option<String>,
+    pub kind: Option<ProfileKind>,
+    pub codec: Option<String>,
+    pub battery: Option<u8>,
+    pub volume: Option<u8>,
+}
+
+impl StatusLine {
+    pub fn render(&self, glyphs: StatusGlyphs) -> String {
+        let mut fields: Vec<String> = Vec::new();
+
+        if let Some(name) = &self.name {
+            match glyphs {
+                StatusGlyphs::Unicode => fields.push(format!("🎧 {name}")),
+                StatusGlyphs::Ascii => fields.push(name.clone()),
+            }
+        }
+
+        match (self.kind, &self.codec) {
+            (Some(kind), Some(codec)) => {
+                fields.push(format!("{}/{}", kind.label(), codec_label(codec)))
+            }
+            (Some(kind), None) => fields.push(kind.label().to_string()),
+            (None, Some(codec)) => fields.push(codec_label(codec)),
+            (None, None) => {}
+        }
+
+        if let Some(battery) = self.battery {
+            fields.push(format!("{battery}%"));
+        }
+
+        if let Some(volume) = self.volume {
+            fields.push(format!("vol {volume}%"));
+        }
+
+        fields.join("  ")
+    }
+}
+
+/// Compact one-line summary for shells and status bars,
+/// e.g. `🎧 WH-1000XM4  A2DP/LDAC  82%  vol 65%`.
+pub fn status_line(addr: &Address) -> String {
+    status_line_with(addr, StatusGlyphs::default())
+}
+
+pub fn status_line_with(addr: &Address, glyphs: StatusGlyphs) -> String {
+    // Fake devices have no BlueZ object to read an alias or battery from
+    let (alias, battery) = if mock::is_active() {
+        (None, None)
+    } else {
+        bluez::alias_and_battery(addr).unwrap_or_default()
+    };
+
+    status_line_for(get_audio_device(addr).as_ref(), alias, battery).render(glyphs)
+}
+
+/// The audio backend's name wins over the BlueZ alias, battery only comes from BlueZ.
+fn status_line_for(
+    device: Option<&AudioDevice>,
+    alias: Option<String>,
+    battery: Option<u8>,
+) -> StatusLine {
+    StatusLine {
+        name: device.and_then(|d| d.name.clone()).or(alias),
+        kind: device
+            .and_then(AudioDevice::active_profile)
+            .map(AudioProfile::kind),
+        codec: device.and_then(|d| d.codec.clone()),
+        battery,
+        volume: device.and_then(|d| d.volume),
+    }
+}
+
+/// Bitrate the active codec negotiated, e.g. 990 for LDAC at its best. `None` when
+/// the backend does not report one, which is the usual case for SBC and on
+/// PulseAudio. Fake devices report the `bitrate` of the mock dataset.
+pub fn active_bitrate_kbps(addr: &Address) -> Option<u32> {
+    if let Some(bitrate) = mock::bitrate_kbps(addr) {
+        return bitrate;
+    }
+
+    pipewire_bitrate_kbps(&pipewire_snapshot()?, addr)
+}
+
+/// The bitrate on the device's sink node, else on the device object itself.
+fn pipewire_bitrate_kbps(entries: &[PwDumpEntry], addr: &Address) -> Option<u32> {
+    let AudioDeviceId::Pipewire(id) = parse_pipewire_device(entries, addr)?.id else {
+        return None;
+    };
+
+    let from_sink = pipewire_sink_node(entries, id)
+        .and_then(|node| bitrate_kbps(&node.props()?.bluez5_bitrate));
+    from_sink.or_else(|| {
+        let device = entries.iter().find(|e| e.id == id)?;
+        bitrate_kbps(&device.props()?.bluez5_bitrate)
+    })
+}
+
+/// What the link sounds like in a few words, e.g. `LDAC 990kbps 2ch`.
+pub fn audio_quality_summary(addr: &Address) -> Option<String> {
+    let device = get_audio_device(addr)?;
+
+    let mut fields = vec![codec_label(device.codec.as_deref()?)];
+    if let Some(kbps) = active_bitrate_kbps(addr) {
+        fields.push(format!("{kbps}kbps"));
+    }
+    if let Some(format) = device.format.as_ref().and_then(AudioFormat::label) {
+        fields.push(format);
+    }
+
+    Some(fields.join(" "))
+}
+
+// ── PipeWire backend ───────────────────────────────────────────────
+
+#[derive(Deserialize)]
+struct PwDumpEntry {
+    id: u32,
+    #[serde(rename = "type", default)]
+    kind: String,
+    #[serde(default)]
+    info: Option<PwInfo>,
+    /// Only set on `PipeWire:Interface:Metadata` objects
+    #[serde(default)]
+    metadata: Vec<PwMetadata>,
+}
+
+#[derive(Deserialize)]
+struct PwMetadata {
+    #[serde(default)]
+    key: String,
+    #[serde(default)]
+    value: serde_json::Value,
+}
+
+impl PwDumpEntry {
+    fn props(&self) -> Option<&PwProps> {
+        self.info.as_ref()?.props.as_ref()
+    }
+
+    fn params(&self) -> Option<&PwParams> {
+        self.info.as_ref()?.params.as_ref()
+    }
+}
+
+#[derive(Deserialize)]
+struct PwInfo {
+    #[serde(default)]
+    props: Option<PwProps>,
+    #[serde(default)]
+    params: Option<PwParams>,
+    /// Only set on `PipeWire:Interface:Link` objects
+    #[serde(rename = "output-node-id", default)]
+    output_node_id: Option<u32>,
+    #[serde(rename = "input-node-id", default)]
+    input_node_id: Option<u32>,
+    /// Node state: `running`, `idle`, `suspended`, ...
+    #[serde(default)]
+    state: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct PwProps {
+    #[serde(rename = "api.bluez5.address")]
+    bluez5_address: Option<String>,
+    #[serde(rename = "api.bluez5.codec")]
+    bluez5_codec: Option<String>,
+    #[serde(rename = "media.class")]
+    media_class: Option<String>,
+    #[serde(rename = "device.id")]
+    device_id: Option<u32>,
+    #[serde(rename = "device.description")]
+    device_description: Option<String>,
+    #[serde(rename = "device.alias")]
+    device_alias: Option<String>,
+    /// `bluez_card.XX_XX_XX_XX_XX_XX`
+    #[serde(rename = "device.name", default)]
+    device_name: Option<String>,
+    #[serde(rename = "node.name")]
+    node_name: Option<String>,
+    #[serde(rename = "audio.channels", default)]
+    audio_channels: Option<u8>,
+    /// `true` or `"true"` when the node forwards volume to the headset
+    #[serde(rename = "api.bluez5.hw-volume", default)]
+    bluez5_hw_volume: serde_json::Value,
+    /// `"FL,FR"`, sometimes `"[ FL FR ]"`
+    #[serde(rename = "audio.position", default)]
+    audio_position: serde_json::Value,
+    /// Bits per second as a number, or a string such as `"990kbps"`
+    #[serde(rename = "api.bluez5.bitrate", alias = "bluez5.bitrate", default)]
+    bluez5_bitrate: serde_json::Value,
+}
+
+#[derive(Deserialize)]
+struct PwParams {
+    #[serde(rename = "EnumProfile", default)]
+    enum_profile: Vec<PwEnumProfile>,
+    #[serde(rename = "Profile", default)]
+    profile: Vec<PwActiveProfile>,
+    #[serde(rename = "Props", default)]
+    props: Vec<PwNodeProps>,
+    #[serde(rename = "Format", default)]
+    format: Vec<PwFormat>,
+    #[serde(rename = "EnumFormat", default)]
+    enum_format: Vec<PwFormat>,
+}
+
+#[derive(Deserialize)]
+struct PwFormat {
+    #[serde(default)]
+    channels: Option<u8>,
+    /// `["FL", "FR"]`
+    #[serde(default)]
+    position: serde_json::Value,
+    /// A number, or a choice such as `{"default": 48000, "alt1": 44100, "alt2": 96000}`
+    #[serde(default)]
+    rate: serde_json::Value,
+}
+
+#[derive(Deserialize)]
+struct PwNodeProps {
+    #[serde(rename = "channelVolumes", default)]
+    channel_volumes: Vec<f32>,
+}
+
+#[derive(Deserialize)]
+struct PwEnumProfile {
+    index: u32,
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    description: Option<String>,
+    #[serde(default)]
+    available: Option<String>,
+    /// `[2, ["Audio/Sink", 1, "card.profile.devices", [0]], ["Audio/Source", 1, ...]]`
+    #[serde(default)]
+    classes: serde_json::Value,
+}
+
+#[derive(Deserialize)]
+struct PwActiveProfile {
+    index: u32,
+}
+
+/// Parse `XX_XX_XX_XX_XX_XX` (as used in bluez object names) or the colon form.
+fn address_from_bluez_format(s: &str) -> Option<Address> {
+    Address::from_str(&s.replace("_", ":")).ok()
+}
+
+const PW_TYPE_DEVICE: &str = "PipeWire:Interface:Device";
+const PW_TYPE_LINK: &str = "PipeWire:Interface:Link";
+
+fn pipewire_profile_category(profile: &PwEnumProfile) -> ProfileCategory {
+    let Some(classes) = profile.classes.as_array() else {
+        let kind = ProfileKind::from_profile_name(profile.name.as_deref().unwrap_or_default());
+        return ProfileCategory::from_kind(kind);
+    };
+
+    let count = |class: &str| -> u32 {
+        classes
+            .iter()
+            .filter_map(|c| c.as_array())
+            .filter(|c| c.first().and_then(|v| v.as_str()) == Some(class))
+            .filter_map(|c| c.get(1).and_then(|v| v.as_u64()))
+            .sum::<u64>() as u32
+    };
+    ProfileCategory::from_counts(count("Audio/Sink"), count("Audio/Source"))
+}
+
+fn pipewire_snapshot() -> Option<Vec<PwDumpEntry>> {
+    let output = run("pw-dump", &[]).ok()?;
+    if !output.status.success() {
+        return None;
+    }
+
+    serde_json::from_slice(&output.stdout).ok()
+}
+
+/// Parse `pw-dump` one object at a time, stopping as soon as `done` is satisfied
+/// with what was read so far. Objects that fail to parse are skipped.
+fn pipewire_snapshot_until(
+    mut done: impl FnMut(&[PwDumpEntry]) -> bool,
+) -> Option<Vec<PwDumpEntry>> {
+    let mut child = spawn_piped("pw-dump", &[]).ok()?;
+    let stdout = child.stdout.take()?;
+
+    let (entries, finished_early) = read_entries_until(BufReader::new(stdout), &mut done);
+
+    if finished_early {
+        let _ = child.kill();
+    }
+    let status = child.wait().ok()?;
+    (finished_early || status.success()).then_some(entries)
+}
+
+/// Read dump objects off `reader` until `done` is satisfied, and whether it was
+/// before the end of the array.
+fn read_entries_until(
+    reader: impl BufRead,
+    mut done: impl FnMut(&[PwDumpEntry]) -> bool,
+) -> (Vec<PwDumpEntry>, bool) {
+    let mut entries = Vec::new();
+    for object in JsonArrayElements::new(reader) {
+        let Ok(entry) = serde_json::from_slice::<PwDumpEntry>(&object) else {
+            continue;
+        };
+        entries.push(entry);
+        if done(&entries) {
+            return (entries, true);
+        }
+    }
+    (entries, false)
+}
+
+/// Splits a top-level JSON array into the raw bytes of its elements without
+/// parsing them, so big dumps can be handled element by element.
+struct JsonArrayElements<R> {
+    bytes: io::Bytes<R>,
+    /// The opening `[` was read
+    in_array: bool,
+}
+
+impl<R: BufRead> JsonArrayElements<R> {
+    fn new(reader: R) -> Self {
+        Self {
+            bytes: reader.bytes(),
+            in_array: false,
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for JsonArrayElements<R> {
+    type Item = Vec<u8>;
+
+    fn next(&mut self) -> Option<Vec<u8>> {
+        let mut element = Vec::new();
+        // Depth counts the outer array, elements live at depth 1
+        let mut depth = usize::from(self.in_array);
+        let mut in_string = false;
+        let mut escaped = false;
+
+        for byte in self.bytes.by_ref() {
+            let byte = byte.ok()?;
+
+            if in_string {
+                element.push(byte);
+                match byte {
+                    _ if escaped => escaped = false,
+                    b'\\' => escaped = true,
+                    b'"' => in_string = false,
+                    _ => {}
+                }
+                continue;
+            }
+
+            match byte {
+                b'[' if depth == 0 => {
+                    self.in_array = true;
+                    depth = 1;
+                    continue;
+                }
+                b'[' | b'{' => depth += 1,
+                b']' | b'}' if depth <= 1 => return (!element.is_empty()).then_some(element),
+                b']' | b'}' => depth -= 1,
+                b',' if depth == 1 => {
+                    if element.is_empty() {
+                        continue;
+                    }
+                    return Some(element);
+                }
+                b'"' => in_string = true,
+                _ if byte.is_ascii_whitespace() && depth == 1 => continue,
+                _ => {}
+            }
+            if depth >= 1 {
+                element.push(byte);
+            }
+        }
+
+        (!element.is_empty()).then_some(element)
+    }
+}
+
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(skip_all, fields(addr = %addr, backend = "pipewire"))
+)]
+fn get_pipewire_device(addr: &Address) -> Option<AudioDevice> {
+    // Stop reading once the device and its sink are in, most of a big graph is
+    // streams and clients that do not matter here
+    let entries = pipewire_snapshot_until(|entries| pipewire_lookup_complete(entries, addr))?;
+    parse_pipewire_device(&entries, addr)
+}
+
+/// The active device object for `addr` and its sink node were both read, checked
+/// whenever the last entry read belongs to `addr`. Devices without an active
+/// profile may be a stale duplicate, so they never end the read.
+fn pipewire_lookup_complete(entries: &[PwDumpEntry], addr: &Address) -> bool {
+    if entries.last().and_then(pipewire_entry_address) != Some(*addr) {
+        return false;
+    }
+    entries
+        .iter()
+        .filter(|e| e.kind == PW_TYPE_DEVICE && pipewire_entry_address(e) == Some(*addr))
+        .filter(|e| e.params().is_some_and(|p| !p.profile.is_empty()))
+        .any(|device| pipewire_sink_node(entries, device.id).is_some())
+}
+
+fn parse_pipewire_device(entries: &[PwDumpEntry], addr: &Address) -> Option<AudioDevice> {
+    let devices = entries
+        .iter()
+        .filter(|entry| pipewire_entry_address(entry) == Some(*addr))
+        .filter_map(|entry| pipewire_device(entries, entry));
+
+    dedup_pipewire_devices(devices).into_iter().next()
+}
+
+fn parse_pipewire_devices(entries: &[PwDumpEntry]) -> Vec<AudioDevice> {
+    dedup_pipewire_devices(
+        entries
+            .iter()
+            .filter_map(|entry| pipewire_device(entries, entry)),
+    )
+}
+
+/// While a device reconnects PipeWire can list a stale device object next to the
+/// new one for the same MAC. Keep one per address, the one with an active profile.
+///
+/// Duplicates are dropped silently unless bluetui runs with `--event-log`, which
+/// writes a `duplicate` entry with `old` the object dropped and `new` the one
+/// kept, or is built with the `tracing` feature, which logs a warning.
+fn dedup_pipewire_devices(devices: impl IntoIterator<Item = AudioDevice>) -> Vec<AudioDevice> {
+    let mut kept: Vec<AudioDevice> = Vec::new();
+
+    for device in devices {
+        let Some(existing) = kept.iter_mut().find(|d| d.address == device.address) else {
+            kept.push(device);
+            continue;
+        };
+
+        #[cfg(feature = "tracing")]
+        tracing::warn!(
+            addr = %device.address,
+            ids = ?(&existing.id, &device.id),
+            "duplicate pipewire device objects"
+        );
+
+        let object = |id: &AudioDeviceId| match id {
+            AudioDeviceId::Pipewire(id) => id.to_string(),
+            AudioDeviceId::Pulseaudio(card) => card.clone(),
+        };
+        let (stale, fresh) =
+            if existing.active_profile_index.is_none() && device.active_profile_index.is_some() {
+                (std::mem::replace(existing, device), &*existing)
+            } else {
+                (device, &*existing)
+            };
+        event_log::record(
+            fresh
+                .id
+                .event("duplicate", Some(fresh.address), object(&fresh.id))
+                .old(Some(object(&stale.id))),
+        );
+    }
+
+    kept
+}
+
+fn pipewire_entry_address(entry: &PwDumpEntry) -> Option<Address> {
+    address_from_bluez_format(entry.props()?.bluez5_address.as_deref()?)
+}
+
+/// Build the device for `entry` if it is a bluez device object. Devices that do not
+/// expose their profiles yet come back as [`AudioDevice::pending`].
+fn pipewire_device(entries: &[PwDumpEntry], entry: &PwDumpEntry) -> Option<AudioDevice> {
+    let address = pipewire_entry_address(entry)?;
+    let props = entry.props()?;
+    let name = props
+        .device_alias
+        .clone()
+        .or_else(|| props.device_description.clone());
+
+    let Some(params) = entry.params().filter(|p| !p.enum_profile.is_empty()) else {
+        // Nodes carry the bluez address too, only the device object stands for the card
+        return (entry.kind == PW_TYPE_DEVICE)
+            .then(|| AudioDevice::pending(AudioDeviceId::Pipewire(entry.id), address, name));
+    };
+
+    let profiles: Vec<AudioProfile> = params
+        .enum_profile
+        .iter()
+        .filter(|p| p.name.as_deref() != Some("off"))
+        .map(|p| AudioProfile {
+            index: p.index,
+            name: p.name.clone().unwrap_or_default(),
+            description: p.description.clone().unwrap_or_default(),
+            available: pipewire_available(p.available.as_deref()),
+            category: pipewire_profile_category(p),
+        })
+        .filter(|p| p.available)
+        .collect();
+
+    let active_profile_index = params.profile.first().map(|p| p.index);
+
+    // Codec and volume live on the sink node created for the device
+    let sink = pipewire_sink_node(entries, entry.id);
+
+    let codec = sink
+        .and_then(|s| s.props()?.bluez5_codec.clone())
+        .or_else(|| {
+            profiles
+                .iter()
+                .find(|p| Some(p.index) == active_profile_index)
+                .and_then(AudioProfile::codec)
+        });
+
+    let volume = sink
+        .and_then(|s| s.params()?.props.first())
+        .and_then(|p| pipewire_volume_percent(&p.channel_volumes));
+
+    let format = sink.and_then(pipewire_format);
+
+    Some(AudioDevice {
+        id: AudioDeviceId::Pipewire(entry.id),
+        address,
+        name,
+        profiles,
+        active_profile_index,
+        codec,
+        volume,
+        format,
+        pending: false,
+    })
+}
+
+/// Channel layout of a node, from its negotiated `Format` param or, before anything
+/// was negotiated, its `audio.*` props.
+fn pipewire_format(node: &PwDumpEntry) -> Option<AudioFormat> {
+    if let Some(format) = node.params().and_then(|p| p.format.first()) {
+        let positions = channel_positions(&format.position);
+        if let Some(format) = AudioFormat::from_positions(format.channels, &positions) {
+            return Some(format);
+        }
+    }
+
+    let props = node.props()?;
+    AudioFormat::from_positions(
+        props.audio_channels,
+        &channel_positions(&props.audio_position),
+    )
+}
+
+fn json_truthy(value: &serde_json::Value) -> bool {
+    match value {
+        serde_json::Value::Bool(b) => *b,
+        serde_json::Value::String(s) => pipewire_available(Some(s)),
+        _ => false,
+    }
+}
+
+/// Values above 10000 are taken as bits per second, smaller ones as kbps already.
+fn bitrate_kbps(value: &serde_json::Value) -> Option<u32> {
+    let raw = match value {
+        serde_json::Value::Number(n) => n.as_u64()?,
+        serde_json::Value::String(s) => {
+            let s = s.trim().to_ascii_lowercase();
+            if let Some(kbps) = s.strip_suffix("kbps") {
+                return kbps.trim().parse().ok();
+            }
+            s.trim_end_matches("bps").trim().parse().ok()?
+        }
+        _ => return None,
+    };
+
+    let kbps = if raw > 10_000 { raw / 1000 } else { raw };
+    u32::try_from(kbps).ok().filter(|&kbps| kbps > 0)
+}
+
+fn pipewire_sample_rates(node: &PwDumpEntry) -> Vec<u32> {
+    let Some(params) = node.params() else {
+        return Vec::new();
+    };
+
+    let mut rates: Vec<u32> = params
+        .enum_format
+        .iter()
+        .chain(&params.format)
+        .flat_map(|format| sample_rates(&format.rate))
+        .collect();
+    rates.sort_unstable();
+    rates.dedup();
+    rates
+}
+
+/// Rates out of a SPA value: a plain number, a list, or an enum choice whose
+/// `default`/`alt*` members are the options. Ranges only contribute their default.
+fn sample_rates(value: &serde_json::Value) -> Vec<u32> {
+    let rate = |v: &serde_json::Value| v.as_u64().and_then(|r| u32::try_from(r).ok());
+    match value {
+        serde_json::Value::Array(items) => items.iter().filter_map(rate).collect(),
+        serde_json::Value::Object(choice) => choice
+            .iter()
+            .filter(|(key, _)| *key == "default" || key.starts_with("alt"))
+            .filter_map(|(_, v)| rate(v))
+            .collect(),
+        value => rate(value).into_iter().collect(),
+    }
+}
+
+/// Accept positions as a JSON array or as a comma/space separated string.
+fn channel_positions(value: &serde_json::Value) -> Vec<String> {
+    match value {
+        serde_json::Value::Array(items) => items
+            .iter()
+            .filter_map(|v| v.as_str())
+            .map(str::to_string)
+            .collect(),
+        serde_json::Value::String(s) => split_channel_positions(s),
+        _ => Vec::new(),
+    }
+}
+
+fn split_channel_positions(s: &str) -> Vec<String> {
+    s.split(|c: char| c == ',' || c.is_whitespace() || c == '[' || c == ']')
+        .filter(|p| !p.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// PipeWire reports profile availability as `"yes"`, `"no"` or `"unknown"`. Compare
+/// leniently so a change in case or padding doesn't mark every profile unavailable.
+fn pipewire_available(value: Option<&str>) -> bool {
+    match value.map(|v| v.trim().to_ascii_lowercase()).as_deref() {
+        Some("yes" | "true") => true,
+        // "no", "unknown" and anything unexpected
+        _ => false,
+    }
+}
+
+fn pipewire_sink_node(entries: &[PwDumpEntry], device_id: u32) -> Option<&PwDumpEntry> {
+    entries.iter().find(|e| {
+        e.props().is_some_and(|p| {
+            p.device_id == Some(device_id) && p.media_class.as_deref() == Some("Audio/Sink")
+        })
+    })
+}
+
+fn pipewire_default_device(entries: &[PwDumpEntry]) -> Option<Address> {
+    // {"key": "default.audio.sink", "value": {"name": "bluez_output.XX_XX_XX_XX_XX_XX.1"}}
+    let sink_name = entries
+        .iter()
+        .flat_map(|e| &e.metadata)
+        .find(|m| m.key == "default.audio.sink")?
+        .value
+        .get("name")?
+        .as_str()?;
+
+    let node = entries
+        .iter()
+        .find(|e| e.props().and_then(|p| p.node_name.as_deref()) == Some(sink_name))?;
+
+    // bluez nodes carry the address themselves, fall back to their device object
+    pipewire_entry_address(node).or_else(|| {
+        let device_id = node.props()?.device_id?;
+        entries
+            .iter()
+            .find(|e| e.id == device_id)
+            .and_then(pipewire_entry_address)
+    })
+}
+
+/// Whether any node of the device is moving audio. On a headset profile the nodes only
+/// run while the SCO link is up.
+fn pipewire_device_running(entries: &[PwDumpEntry], device_id: u32) -> bool {
+    entries
+        .iter()
+        .filter(|e| e.props().is_some_and(|p| p.device_id == Some(device_id)))
+        .any(|e| e.info.as_ref().and_then(|i| i.state.as_deref()) == Some("running"))
+}
+
+fn pipewire_device_suspended(entries: &[PwDumpEntry], device_id: u32) -> Option<bool> {
+    let states: Vec<&str> = entries
+        .iter()
+        .filter(|e| e.props().is_some_and(|p| p.device_id == Some(device_id)))
+        .filter_map(|e| e.info.as_ref()?.state.as_deref())
+        .collect();
+
+    (!states.is_empty()).then(|| states.iter().all(|&state| state == "suspended"))
+}
+
+/// Nodes linked to any of the device's nodes, i.e. the streams using it. Links are
+/// per port, so a stereo stream shows up as two links to the same peer.
+fn pipewire_stream_count(entries: &[PwDumpEntry], device_id: u32) -> usize {
+    let nodes: Vec<u32> = entries
+        .iter()
+        .filter(|e| e.props().is_some_and(|p| p.device_id == Some(device_id)))
+        .map(|e| e.id)
+        .collect();
+
+    let mut peers: Vec<u32> = entries
+        .iter()
+        .filter(|e| e.kind == PW_TYPE_LINK)
+        .filter_map(|e| {
+            let info = e.info.as_ref()?;
+            match (info.output_node_id, info.input_node_id) {
+                (Some(out), Some(input)) if nodes.contains(&out) => Some(input),
+                (Some(out), Some(input)) if nodes.contains(&input) => Some(out),
+                _ => None,
+            }
+        })
+        .collect();
+    peers.sort_unstable();
+    peers.dedup();
+    peers.len()
+}
+
+/// PipeWire stores linear channel volumes; `wpctl` shows their cubic root.
+fn pipewire_volume_percent(channel_volumes: &[f32]) -> Option<u8> {
+    if channel_volumes.is_empty() {
+        return None;
+    }
+    let avg = channel_volumes.iter().sum::<f32>() / channel_volumes.len() as f32;
+    Some((avg.cbrt() * 100.0).round().clamp(0.0, 255.0) as u8)
+}
+
+/// `wpctl set-profile` takes the id of the device object; passing the id of one of
+/// its nodes fails with an unhelpful message, so check the snapshot first.
+fn validate_pipewire_device_id(
+    entries: &[PwDumpEntry],
+    device_id: u32,
+) -> Result<(), ProfileError> {
+    let entry = entries
+        .iter()
+        .find(|e| e.id == device_id)
+        .ok_or(ProfileError::DeviceNotFound(device_id))?;
+
+    let media_class = entry.props().and_then(|p| p.media_class.as_deref());
+    if entry.kind == PW_TYPE_DEVICE || media_class == Some("Audio/Device") {
+        return Ok(());
+    }
+
+    Err(ProfileError::NotADevice {
+        id: device_id,
+        media_class: media_class.unwrap_or(&entry.kind).to_string(),
+    })
+}
+
+#[cfg_attr(feature = "tracing", tracing::instrument(fields(backend = "pipewire")))]
+fn switch_pipewire_profile(
+    device_id: u32,
+    profile_index: u32,
+) -> Result<BackendResult, ProfileError> {
+    // A missing snapshot is not fatal, wpctl reports its own errors
+    if let Some(entries) = pipewire_snapshot() {
+        validate_pipewire_device_id(&entries, device_id)?;
+    }
+
+    run_raw(
+        "wpctl",
+        &[
+            "set-profile",
+            &device_id.to_string(),
+            &profile_index.to_string(),
+        ],
+    )
+}
+
+// ── PulseAudio backend ─────────────────────────────────────────────
+
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(skip_all, fields(addr = %addr, backend = "pulseaudio"))
+)]
+fn get_pulseaudio_device(addr: &Address) -> Option<AudioDevice> {
+    let cards = pulseaudio_cards()?;
+    let card = cards
+        .iter()
+        .find(|card| pulseaudio_card_address(card) == Some(*addr))?;
+
+    pulseaudio_device(card, &pulseaudio_sinks())
+}
+
+fn pulseaudio_cards() -> Option<Vec<PaCard>> {
+    let output = run("pactl", &["--format=json", "list", "cards"]).ok()?;
+    if !output.status.success() {
+        return None;
+    }
+
+    serde_json::from_slice(&output.stdout).ok()
+}
+
+fn pulseaudio_card_address(card: &PaCard) -> Option<Address> {
+    // Match by bluez5 address in properties
+    card.properties
+        .get("api.bluez5.address")
+        .or_else(|| card.properties.get("device.string"))
+        .and_then(|s| address_from_bluez_format(s))
+        // Also try the card name (bluez_card.XX_XX_XX_XX_XX_XX)
+        .or_else(|| {
+            let rest = card.name.strip_prefix("bluez_card.")?;
+            address_from_bluez_format(rest.get(..17)?)
+        })
+}
+
+fn pulseaudio_device(card: &PaCard, sinks: &[PaSink]) -> Option<AudioDevice> {
+    let address = pulseaudio_card_address(card)?;
+
+    // Some PulseAudio versions list a card before its profiles are known
+    if card.profiles.is_empty() {
+        return Some(AudioDevice::pending(
+            AudioDeviceId::Pulseaudio(card.name.clone()),
+            address,
+            card.properties.get("device.description").cloned(),
+        ));
+    }
+
+    let mut profiles: Vec<AudioProfile> = Vec::new();
+    let mut active_profile_index: Option<u32> = None;
+
+    for (idx, pa_profile) in card.profiles.iter().enumerate() {
+        if pa_profile.name == "off" {
+            continue;
+        }
+        let available = pa_profile.available;
+        let profile = AudioProfile {
+            index: idx as u32,
+            name: pa_profile.name.clone(),
+            description: pa_profile.description.clone(),
+            available,
+            category: match (pa_profile.sinks, pa_profile.sources) {
+                (None, None) => {
+                    ProfileCategory::from_kind(ProfileKind::from_profile_name(&pa_profile.name))
+                }
+                (sinks, sources) => {
+                    ProfileCategory::from_counts(sinks.unwrap_or(0), sources.unwrap_or(0))
+                }
+            },
+        };
+        if available {
+            profiles.push(profile);
+        }
+    }
+
+    // Find active profile, keyed by the same index as `AudioProfile::index`
+    if let Some(ref active_name) = card.active_profile {
+        for p in profiles.iter() {
+            if p.name == *active_name {
+                active_profile_index = Some(p.index);
+                break;
+            }
+        }
+    }
+
+    if profiles.is_empty() {
+        return None;
+    }
+
+    let codec = card
+        .properties
+        .get("api.bluez5.codec")
+        .or_else(|| card.properties.get("bluetooth.codec"))
+        .cloned()
+        .or_else(|| {
+            profiles
+                .iter()
+                .find(|p| Some(&p.name) == card.active_profile.as_ref())
+                .and_then(AudioProfile::codec)
+        });
+
+    Some(AudioDevice {
+        id: AudioDeviceId::Pulseaudio(card.name.clone()),
+        address,
+        name: card.properties.get("device.description").cloned(),
+        profiles,
+        active_profile_index,
+        codec,
+        volume: pulseaudio_sink_volume(sinks, &address),
+        format: pulseaudio_sink(sinks, &address).and_then(|sink| {
+            AudioFormat::from_positions(None, &split_channel_positions(&sink.channel_map))
+        }),
+        pending: false,
+    })
+}
+
+#[derive(Deserialize)]
+struct PaCard {
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    properties: std::collections::HashMap<String, String>,
+    #[serde(default)]
+    profiles: Vec<PaProfile>,
+    #[serde(rename = "active_profile", default)]
+    active_profile: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct PaProfile {
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    description: String,
+    #[serde(default)]
+    available: bool,
+    #[serde(default)]
+    sinks: Option<u32>,
+    #[serde(default)]
+    sources: Option<u32>,
+}
+
+#[derive(Deserialize)]
+struct PaSink {
+    #[serde(default)]
+    index: u32,
+    #[serde(default)]
+    name: String,
+    /// `RUNNING`, `IDLE` or `SUSPENDED`
+    #[serde(default)]
+    state: String,
+    /// `front-left,front-right`
+    #[serde(default)]
+    channel_map: String,
+    /// `s16le 2ch 48000Hz`
+    #[serde(default)]
+    sample_specification: String,
+    /// `HARDWARE`, `HW_VOLUME_CTRL`, `DECIBEL_VOLUME`, ...
+    #[serde(default)]
+    flags: Vec<String>,
+    #[serde(default)]
+    properties: std::collections::HashMap<String, String>,
+    #[serde(default)]
+    volume: std::collections::HashMap<String, PaVolume>,
+}
+
+#[derive(Deserialize)]
+struct PaVolume {
+    #[serde(default)]
+    value_percent: String,
+}
+
+/// `sink` on sink inputs, `source` on source outputs
+#[derive(Deserialize)]
+struct PaStream {
+    #[serde(default)]
+    sink: Option<u32>,
+    #[serde(default)]
+    source: Option<u32>,
+}
+
+fn pulseaudio_list<T: serde::de::DeserializeOwned>(what: &str) -> Vec<T> {
+    let Ok(output) = run("pactl", &["--format=json", "list", what]) else {
+        return Vec::new();
+    };
+    if !output.status.success() {
+        return Vec::new();
+    }
+
+    serde_json::from_slice(&output.stdout).unwrap_or_default()
+}
+
+fn pulseaudio_sinks() -> Vec<PaSink> {
+    pulseaudio_list("sinks")
+}
+
+fn pulseaudio_device_running(addr: &Address) -> bool {
+    let sources: Vec<PaSink> = pulseaudio_list("sources");
+    pulseaudio_sinks()
+        .iter()
+        .chain(&sources)
+        .filter(|s| pulseaudio_sink_address(s) == Some(*addr))
+        .any(|s| s.state == "RUNNING")
+}
+
+/// Streams playing to or recording from the device's sinks and sources.
+fn pulseaudio_stream_count(addr: &Address) -> usize {
+    // Sources have the same shape as sinks as far as we are concerned
+    let sources: Vec<PaSink> = pulseaudio_list("sources");
+    let sinks = pulseaudio_sinks();
+    let indices = |list: &[PaSink]| -> Vec<u32> {
+        list.iter()
+            .filter(|s| pulseaudio_sink_address(s) == Some(*addr))
+            .map(|s| s.index)
+            .collect()
+    };
+    let (sinks, sources) = (indices(&sinks), indices(&sources));
+
+    let inputs: Vec<PaStream> = pulseaudio_list("sink-inputs");
+    let outputs: Vec<PaStream> = pulseaudio_list("source-outputs");
+    inputs
+        .iter()
+        .filter(|s| s.sink.is_some_and(|i| sinks.contains(&i)))
+        .chain(
+            outputs
+                .iter()
+                .filter(|s| s.source.is_some_and(|i| sources.contains(&i))),
+        )
+        .count()
+}
+
+fn pulseaudio_sink<'a>(sinks: &'a [PaSink], addr: &Address) -> Option<&'a PaSink> {
+    sinks
+        .iter()
+        .find(|sink| pulseaudio_sink_address(sink) == Some(*addr))
+}
+
+fn pulseaudio_sink_address(sink: &PaSink) -> Option<Address> {
+    sink.properties
+        .get("api.bluez5.address")
+        .or_else(|| sink.properties.get("device.string"))
+        .and_then(|a| address_from_bluez_format(a))
+}
+
+fn pulseaudio_default_device() -> Option<Address> {
+    let output = run("pactl", &["get-default-sink"]).ok()?;
+    if !output.status.success() {
+        return None;
+    }
+
+    let name = String::from_utf8_lossy(&output.stdout);
+    pulseaudio_sinks()
+        .iter()
+        .find(|sink| sink.name == name.trim())
+        .and_then(pulseaudio_sink_address)
+}
+
+fn pulseaudio_sink_volume(sinks: &[PaSink], addr: &Address) -> Option<u8> {
+    let sink = pulseaudio_sink(sinks, addr)?;
+
+    let percents: Vec<u32> = sink
+        .volume
+        .values()
+        .filter_map(|v| v.value_percent.trim_end_matches('%').trim().parse().ok())
+        .collect();
+    if percents.is_empty() {
+        return None;
+    }
+    let avg = percents.iter().sum::<u32>() / percents.len() as u32;
+    Some(avg.min(u8::MAX as u32) as u8)
+}
+
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(fields(backend = "pulseaudio"))
+)]
+fn switch_pulseaudio_profile(
+    card_name: &str,
+    profile_name: &str,
+) -> Result<BackendResult, ProfileError> {
+    run_raw("pactl", &["set-card-profile", card_name, profile_name])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ADDR: &str = "AA:BB:CC:DD:EE:FF";
+
+    fn addr() -> Address {
+        Address::from_str(ADDR).unwrap()
+    }
+
+    /// A headset on A2DP/LDAC with its sink node right after the device object.
+    const SAMPLE_DUMP: &str = r#"[
+        {
+            "id": 40,
+            "type": "PipeWire:Interface:Device",
+            "info": {
+                "props": {
+                    "api.bluez5.address": "AA:BB:CC:DD:EE:FF",
+                    "device.description": "WH-1000XM4",
+                    "device.name": "bluez_card.AA_BB_CC_DD_EE_FF",
+                    "media.class": "Audio/Device"
+                },
+                "params": {
+                    "EnumProfile": [
+                        {"index": 0, "name": "off", "description": "Off", "available": "yes", "classes": [0]},
+                        {"index": 1, "name": "a2dp-sink", "description": "High Fidelity Playback (A2DP Sink)", "available": "yes",
+                         "classes": [1, ["Audio/Sink", 1, "card.profile.devices", [0]]]},
+                        {"index": 2, "name": "headset-head-unit", "description": "Headset Head Unit (HSP/HFP)", "available": "yes",
+                         "classes": [2, ["Audio/Sink", 1, "card.profile.devices", [1]], ["Audio/Source", 1, "card.profile.devices", [2]]]}
+                    ],
+                    "Profile": [{"index": 1}]
+                }
+            }
+        },
+        {
+            "id": 41,
+            "type": "PipeWire:Interface:Node",
+            "info": {
+                "state": "running",
+                "props": {
+                    "api.bluez5.address": "AA:BB:CC:DD:EE:FF",
+                    "api.bluez5.codec": "ldac",
+                    "api.bluez5.bitrate": 990000,
+                    "device.id": 40,
+                    "media.class": "Audio/Sink",
+                    "node.name": "bluez_output.AA_BB_CC_DD_EE_FF.1"
+                },
+                "params": {
+                    "Props": [{"channelVolumes": [0.274625, 0.274625]}],
+                    "Format": [{"channels": 2, "position": ["FL", "FR"], "rate": 96000}],
+                    "EnumFormat": [{"channels": 2, "rate": {"default": 48000, "alt1": 44100, "alt2": 96000}}]
+                }
+            }
+        },
+        {
+            "id": 42,
+            "type": "PipeWire:Interface:Metadata",
+            "metadata": [
+                {"subject": 0, "key": "default.audio.sink", "type": "Spa:String:JSON",
+                 "value": {"name": "bluez_output.AA_BB_CC_DD_EE_FF.1"}}
+            ]
+        }
+    ]"#;
+
+    /// [`SAMPLE_DUMP`] on the headset profile, its node in `state`.
+    fn headset_dump(state: &str) -> String {
+        SAMPLE_DUMP
+            .replace(
+                r#""Profile": [{"index": 1}]"#,
+                r#""Profile": [{"index": 2}]"#,
+            )
+            .replace(r#""state": "running""#, &format!(r#""state": "{state}""#))
+    }
+
+    fn dump(json: &str) -> Vec<PwDumpEntry> {
+        serde_json::from_str(json).unwrap()
+    }
+
+    fn profile(index: u32, name: &str, category: ProfileCategory) -> AudioProfile {
+        AudioProfile {
+            index,
+            name: name.into(),
+            description: name.into(),
+            available: true,
+            category,
+        }
+    }
+
+    /// Held by tests that use the mock backend or the event log, both process wide.
+    static BACKEND: Mutex<()> = Mutex::new(());
+
+    /// Two profiles on one fake headset, A2DP/LDAC active.
+    const MOCK_DEVICES: &str = r#"[{
+        "address": "AA:BB:CC:DD:EE:FF", "name": "WH-1000XM4", "codec": "ldac",
+        "volume": 60, "active_profile": 1,
+        "profiles": [
+            {"index": 1, "name": "a2dp-sink-ldac", "description": "High Fidelity Playback (A2DP Sink, codec LDAC)"},
+            {"index": 2, "name": "a2dp-sink-aac", "description": "High Fidelity Playback (A2DP Sink, codec AAC)"},
+            {"index": 3, "name": "headset-head-unit", "description": "Headset Head Unit (HSP/HFP)"}
+        ]
+    }]"#;
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("bluetui-test-{}-{name}", std::process::id()))
+    }
+
+    /// Serve `devices` from the mock backend. Keep the guard until the test is done.
+    fn mock_backend(devices: &str) -> std::sync::MutexGuard<'static, ()> {
+        let guard = BACKEND.lock().unwrap_or_else(|e| e.into_inner());
+        let path = temp_path("mock.json");
+        std::fs::write(&path, devices).unwrap();
+        enable_mock_backend(&path).unwrap();
+        let _ = std::fs::remove_file(path);
+        guard
+    }
+
+    fn pa_card(json: &str) -> PaCard {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn status_line_ascii_with_all_fields() {
+        let line = StatusLine {
+            name: Some("WH-1000XM4".into()),
+            kind: Some(ProfileKind::A2dp),
+            codec: Some("ldac".into()),
+            battery: Some(82),
+            volume: Some(65),
+        };
+        assert_eq!(
+            line.render(StatusGlyphs::Ascii),
+            "WH-1000XM4  A2DP/LDAC  82%  vol 65%"
+        );
+    }
+
+    #[test]
+    fn status_line_ascii_leaves_out_missing_fields() {
+        let line = StatusLine {
+            name: Some("Buds".into()),
+            codec: Some("msbc".into()),
+            volume: Some(40),
+            ..Default::default()
+        };
+        assert_eq!(line.render(StatusGlyphs::Ascii), "Buds  mSBC  vol 40%");
+        assert_eq!(StatusLine::default().render(StatusGlyphs::Ascii), "");
+    }
+
+    #[test]
+    fn status_line_falls_back_to_the_bluez_alias() {
+        let line = status_line_for(Some(&ldac_headset()), Some("alias".into()), Some(82));
+        assert_eq!(
+            line.render(StatusGlyphs::Ascii),
+            "WH-1000XM4  A2DP/SBC  82%  vol 65%"
+        );
+
+        let line = status_line_for(None, Some("Buds".into()), None);
+        assert_eq!(line.render(StatusGlyphs::Ascii), "Buds");
+    }
+
+    #[test]
+    fn pulseaudio_active_profile_uses_card_index() {
+        // "off" and the unavailable profile are skipped, so the list position
+        // of the active profile no longer matches its card index
+        let card = pa_card(
+            r#"{
+                "name": "bluez_card.AA_BB_CC_DD_EE_FF",
+                "properties": {"api.bluez5.address": "AA:BB:CC:DD:EE:FF"},
+                "profiles": [
+                    {"name": "off", "description": "Off", "available": true},
+                    {"name": "a2dp-sink-aac", "description": "A2DP (AAC)", "available": false},
+                    {"name": "headset-head-unit", "description": "HSP/HFP", "available": true}
+                ],
+                "active_profile": "headset-head-unit"
+            }"#,
+        );
+
+        let device = pulseaudio_device(&card, &[]).unwrap();
+        assert_eq!(device.address, addr());
+        assert_eq!(device.active_profile_index, Some(2));
+        assert_eq!(device.active_profile_name(), Some("headset-head-unit"));
+    }
+
+    #[test]
+    fn validate_pipewire_device_id_tells_device_from_node() {
+        let entries = dump(SAMPLE_DUMP);
+
+        assert!(validate_pipewire_device_id(&entries, 40).is_ok());
+        assert!(matches!(
+            validate_pipewire_device_id(&entries, 41),
+            Err(ProfileError::NotADevice { id: 41, ref media_class }) if media_class == "Audio/Sink"
+        ));
+        assert!(matches!(
+            validate_pipewire_device_id(&entries, 39),
+            Err(ProfileError::DeviceNotFound(39))
+        ));
+    }
+
+    #[test]
+    fn backend_versions_are_probed_once() {
+        let cache = Mutex::new(None);
+        let mut probes = 0;
+        let mut probe = || {
+            probes += 1;
+            BackendVersions {
+                pipewire: Some("1.2.7".into()),
+                pulseaudio: None,
+            }
+        };
+
+        let first = cached_backend_versions(&cache, &mut probe);
+        let second = cached_backend_versions(&cache, &mut probe);
+        assert_eq!(first, second);
+        assert_eq!(first.pipewire.as_deref(), Some("1.2.7"));
+        assert_eq!(probes, 1);
+    }
+
+    #[test]
+    fn parse_version_prefers_linked_library() {
+        let pactl = "pactl 16.1\nCompiled with libpulse 16.1.0\nLinked with libpulse 16.1.1\n";
+        assert_eq!(parse_version(pactl).as_deref(), Some("16.1.1"));
+
+        let pw_dump = "pw-dump\nCompiled with libpipewire 1.2.7\nLinked with libpipewire 1.2.7\n";
+        assert_eq!(parse_version(pw_dump).as_deref(), Some("1.2.7"));
+
+        assert_eq!(parse_version("pactl 15.0").as_deref(), Some("15.0"));
+        assert_eq!(parse_version("no version here"), None);
+    }
+
+    fn transport(volume: Option<u16>) -> bluez::Transport {
+        bluez::Transport {
+            path: dbus::Path::new("/org/bluez/hci0/dev_AA_BB_CC_DD_EE_FF/fd0").unwrap(),
+            volume,
+        }
+    }
+
+    #[test]
+    fn hardware_volume_is_used_when_preferred_and_supported() {
+        let chosen = hardware_volume_transport(true, || Some(transport(Some(90))));
+        assert_eq!(chosen.and_then(|t| t.volume), Some(90));
+    }
+
+    #[test]
+    fn volume_falls_back_to_software_without_absolute_volume() {
+        assert!(hardware_volume_transport(true, || Some(transport(None))).is_none());
+        assert!(hardware_volume_transport(true, || None).is_none());
+
+        let chosen = hardware_volume_transport(false, || panic!("transport looked up"));
+        assert!(chosen.is_none());
+    }
+
+    #[test]
+    fn codec_matrix_folds_a2dp_codecs_under_one_profile() {
+        let profiles = [
+            profile(1, "a2dp-sink-sbc", ProfileCategory::Output),
+            profile(2, "a2dp-sink-aac", ProfileCategory::Output),
+            profile(3, "a2dp-sink-ldac", ProfileCategory::Output),
+            profile(4, "headset-head-unit", ProfileCategory::Duplex),
+        ];
+
+        let matrix: Vec<(String, Vec<String>)> = codec_matrix(&profiles)
+            .into_iter()
+            .map(|(profile, codecs)| (profile.name, codecs))
+            .collect();
+        assert_eq!(
+            matrix,
+            vec![
+                // No plain a2dp-sink, the first variant stands in for it
+                (
+                    "a2dp-sink-sbc".to_string(),
+                    vec!["sbc".into(), "aac".into(), "ldac".into()]
+                ),
+                ("headset-head-unit".to_string(), vec![]),
+            ]
+        );
+    }
+
+    fn output(wait_status: i32, stdout: &str, stderr: &str) -> Output {
+        use std::os::unix::process::ExitStatusExt;
+        Output {
+            status: std::process::ExitStatus::from_raw(wait_status),
+            stdout: stdout.as_bytes().to_vec(),
+            stderr: stderr.as_bytes().to_vec(),
+        }
+    }
+
+    #[test]
+    fn backend_result_keeps_code_and_output() {
+        let ok = BackendResult::from(output(0, "Success\n", ""));
+        assert!(ok.success());
+        assert_eq!(ok.stdout, "Success\n");
+        assert!(ok.check("wpctl").is_ok());
+
+        // Exit code 1 is stored in the high byte of the wait status
+        let failed = BackendResult::from(output(1 << 8, "", "Failure: No such entity\n"));
+        assert_eq!(failed.code, 1);
+        assert!(matches!(
+            failed.check("pactl"),
+            Err(ProfileError::CommandFailed { command: "pactl", ref stderr })
+                if stderr == "Failure: No such entity\n"
+        ));
+
+        // SIGKILL, no exit code
+        let killed = BackendResult::from(output(9, "", ""));
+        assert_eq!(killed.code, -1);
+        assert!(!killed.success());
+    }
+
+    #[test]
+    fn pipewire_default_device_follows_default_sink_metadata() {
+        assert_eq!(pipewire_default_device(&dump(SAMPLE_DUMP)), Some(addr()));
+
+        // A sink without the address falls back to its device object
+        let without_node_address = SAMPLE_DUMP.replacen(
+            r#""api.bluez5.address": "AA:BB:CC:DD:EE:FF",
+                    "api.bluez5.codec""#,
+            r#""api.bluez5.codec""#,
+            1,
+        );
+        assert_ne!(without_node_address, SAMPLE_DUMP);
+        assert_eq!(
+            pipewire_default_device(&dump(&without_node_address)),
+            Some(addr())
+        );
+
+        let non_bluetooth = SAMPLE_DUMP.replace(
+            r#"{"name": "bluez_output.AA_BB_CC_DD_EE_FF.1"}"#,
+            r#"{"name": "alsa_output.pci-0000_00_1f.3.analog-stereo"}"#,
+        );
+        assert_eq!(pipewire_default_device(&dump(&non_bluetooth)), None);
+    }
+
+    #[test]
+    fn pipewire_available_ignores_case_and_padding() {
+        for value in ["yes", "Yes", " yes ", "YES", "true"] {
+            assert!(pipewire_available(Some(value)), "{value:?}");
+        }
+        for value in ["no", "unknown", ""] {
+            assert!(!pipewire_available(Some(value)), "{value:?}");
+        }
+        assert!(!pipewire_available(None));
+    }
+
+    fn ldac_headset() -> AudioDevice {
+        AudioDevice {
+            id: AudioDeviceId::Pipewire(40),
+            address: addr(),
+            name: Some("WH-1000XM4".into()),
+            profiles: vec![
+                profile(1, "a2dp-sink-sbc", ProfileCategory::Output),
+                profile(2, "a2dp-sink-aac", ProfileCategory::Output),
+                profile(3, "a2dp-sink-ldac", ProfileCategory::Output),
+                profile(4, "headset-head-unit", ProfileCategory::Duplex),
+            ],
+            active_profile_index: Some(1),
+            codec: Some("sbc".into()),
+            volume: Some(65),
+            format: None,
+            pending: false,
+        }
+    }
+
+    #[test]
+    fn preferred_codec_picks_ldac_by_quality() {
+        let available = available_codecs(&ldac_headset());
+        assert_eq!(available, ["sbc", "aac", "ldac"]);
+        assert_eq!(preferred_codec(&available, &[]).as_deref(), Some("ldac"));
+        assert_eq!(preferred_codec(&[], &[]), None);
+    }
+
+    #[test]
+    fn profile_switch_logs_one_event_line() {
+        let _backend = mock_backend(MOCK_DEVICES);
+        let log = temp_path("events.jsonl");
+        let _ = std::fs::remove_file(&log);
+        enable_event_log(&log).unwrap();
+
+        let device = get_audio_device(&addr()).unwrap();
+        let aac = device.profiles.iter().find(|p| p.index == 2).unwrap();
+        switch_device_profile(&device, aac, false).unwrap();
+        disable_event_log();
+
+        // The writer thread flushes once the sender is gone
+        let mut text = String::new();
+        for _ in 0..100 {
+            text = std::fs::read_to_string(&log).unwrap_or_default();
+            if text.ends_with('\n') {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        let _ = std::fs::remove_file(&log);
+
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 1, "{text}");
+        let event: serde_json::Value = serde_json::from_str(lines[0]).unwrap();
+        assert_eq!(event["kind"], "profile");
+        assert_eq!(event["backend"], "pipewire");
+        assert_eq!(event["address"], ADDR);
+        assert_eq!(event["old"], "a2dp-sink-ldac");
+        assert_eq!(event["new"], "a2dp-sink-aac");
+        assert_eq!(event["success"], true);
+        assert!(event["timestamp"].as_u64().is_some_and(|t| t > 0));
+    }
+
+    #[test]
+    fn restore_puts_profile_codec_and_volume_back() {
+        let _backend = mock_backend(MOCK_DEVICES);
+        let snapshot = snapshot_all();
+
+        let device = get_audio_device(&addr()).unwrap();
+        let headset = device.profiles.iter().find(|p| p.index == 3).unwrap();
+        switch_device_profile(&device, headset, false).unwrap();
+        set_volume(&addr(), 20, false).unwrap();
+
+        let results = restore(&snapshot);
+        assert_eq!(results.len(), 1);
+        assert!(results[0].1.is_ok(), "{:?}", results[0].1);
+
+        let device = get_audio_device(&addr()).unwrap();
+        assert_eq!(device.active_profile_name(), Some("a2dp-sink-ldac"));
+        assert_eq!(device.codec.as_deref(), Some("ldac"));
+        assert_eq!(device.volume, Some(60));
+    }
+
+    #[test]
+    fn restore_switches_codec_within_the_profile() {
+        let _backend = mock_backend(MOCK_DEVICES);
+        let device = get_audio_device(&addr()).unwrap();
+
+        let saved = DeviceSnapshot {
+            address: addr(),
+            profile: None,
+            codec: Some("aac".into()),
+            volume: None,
+            default_output: false,
+        };
+        restore_device(&device, &saved).unwrap();
+
+        let device = get_audio_device(&addr()).unwrap();
+        assert_eq!(device.codec.as_deref(), Some("aac"));
+        assert_eq!(device.active_profile_name(), Some("a2dp-sink-aac"));
+    }
+
+    #[test]
+    fn truncate_profile_label_counts_chars() {
+        assert_eq!(truncate_profile_label("A2DP Sink", 9), "A2DP Sink");
+        assert_eq!(truncate_profile_label("A2DP Sink", 5), "A2DP…");
+        assert_eq!(truncate_profile_label("A2DP Sink", 1), "…");
+        assert_eq!(truncate_profile_label("A2DP Sink", 0), "");
+
+        // Four chars, twelve bytes
+        assert_eq!(truncate_profile_label("高音質再", 4), "高音質再");
+        assert_eq!(truncate_profile_label("高音質再生", 4), "高音質…");
+        assert_eq!(truncate_profile_label("Kopfhörer", 6), "Kopfh…");
+    }
+
+    #[test]
+    fn leaving_a_call_for_a2dp_disrupts_it() {
+        let mut device = ldac_headset();
+        device.active_profile_index = Some(4);
+
+        // Mid-call, A2DP has no microphone
+        assert!(disrupts_call(&device, 1, || 2));
+        // Same switch with nothing running
+        assert!(!disrupts_call(&device, 1, || 0));
+        // Staying on a profile with a microphone
+        assert!(!disrupts_call(&device, 4, || 2));
+
+        // Not on a call profile, the streams are not even counted
+        device.active_profile_index = Some(1);
+        assert!(!disrupts_call(&device, 3, || panic!("streams counted")));
+    }
+
+    #[test]
+    fn sco_active_follows_the_headset_nodes() {
+        assert_eq!(
+            pipewire_sco_active(&dump(&headset_dump("running")), &addr()),
+            Some(true)
+        );
+        assert_eq!(
+            pipewire_sco_active(&dump(&headset_dump("idle")), &addr()),
+            Some(false)
+        );
+
+        // A2DP carries no voice link, whatever its nodes do
+        assert_eq!(pipewire_sco_active(&dump(SAMPLE_DUMP), &addr()), None);
+    }
+
+    #[test]
+    fn reapply_switches_off_then_back() {
+        let device = ldac_headset();
+        let active = device.active_profile().unwrap();
+
+        let mut commands = Vec::new();
+        let result = reapply_with(active, 0, |index, name, previous| {
+            commands.push((index, name.to_string(), previous.to_string()));
+            Ok(String::new())
+        });
+        assert!(result.is_ok());
+        assert_eq!(
+            commands,
+            [
+                (0, "off".into(), "a2dp-sink-sbc".into()),
+                (1, "a2dp-sink-sbc".into(), "off".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn reapply_retries_once_then_reports_the_device_off() {
+        let device = ldac_headset();
+        let active = device.active_profile().unwrap();
+        let failed = || ProfileError::CommandFailed {
+            command: "wpctl",
+            stderr: "busy".into(),
+        };
+
+        // Second attempt at switching back succeeds
+        let mut calls = 0;
+        let result = reapply_with(active, 0, |_, _, _| {
+            calls += 1;
+            if calls == 2 {
+                Err(failed())
+            } else {
+                Ok(String::new())
+            }
+        });
+        assert!(result.is_ok());
+        assert_eq!(calls, 3);
+
+        let mut calls = 0;
+        let result = reapply_with(active, 0, |index, _, _| {
+            calls += 1;
+            if index == 0 {
+                Ok(String::new())
+            } else {
+                Err(failed())
+            }
+        });
+        assert_eq!(calls, 3);
+        assert!(matches!(
+            result,
+            Err(ProfileError::LeftOff { ref profile, .. }) if profile == "a2dp-sink-sbc"
+        ));
+    }
+
+    #[test]
+    fn off_profile_index_comes_from_enum_profile() {
+        let entries = dump(&SAMPLE_DUMP.replace(
+            r#""index": 0, "name": "off""#,
+            r#""index": 7, "name": "off""#,
+        ));
+        assert_eq!(pipewire_off_profile_index(&entries, 40), Some(7));
+        assert_eq!(pipewire_off_profile_index(&entries, 41), None);
+    }
+
+    #[test]
+    fn multichannel_layouts_from_positions() {
+        let positions = |s: &str| split_channel_positions(s);
+
+        let surround = AudioFormat::from_positions(None, &positions("FL,FR,FC,LFE,RL,RR")).unwrap();
+        assert_eq!(surround.channels, Some(6));
+        assert_eq!(surround.label().as_deref(), Some("5.1"));
+
+        // PulseAudio channel map
+        let pulse =
+            "front-left,front-right,front-center,lfe,rear-left,rear-right,side-left,side-right";
+        let format = AudioFormat::from_positions(None, &positions(pulse)).unwrap();
+        assert_eq!(format.label().as_deref(), Some("7.1"));
+
+        let quad = AudioFormat::from_positions(Some(4), &positions("[ FL FR RL RR ]")).unwrap();
+        assert_eq!(quad.layout.as_deref(), Some("quad"));
+
+        // No named layout for five channels
+        let odd = AudioFormat::from_positions(Some(5), &[]).unwrap();
+        assert_eq!(odd.label().as_deref(), Some("5ch"));
+        assert_eq!(AudioFormat::from_positions(None, &[]), None);
+    }
+
+    #[test]
+    fn pipewire_format_falls_back_to_node_props() {
+        let entries = dump(
+            r#"[{"id": 41, "type": "PipeWire:Interface:Node", "info": {"props": {
+                "audio.channels": 6, "audio.position": "FL,FR,FC,LFE,RL,RR"}}}]"#,
+        );
+        let format = pipewire_format(&entries[0]).unwrap();
+        assert_eq!(format.label().as_deref(), Some("5.1"));
+
+        let device = parse_pipewire_device(&dump(SAMPLE_DUMP), &addr()).unwrap();
+        assert_eq!(
+            device.format.and_then(|f| f.label()).as_deref(),
+            Some("2ch")
+        );
+    }
+
+    #[test]
+    fn codec_preference_overrides_the_ranking() {
+        let available = available_codecs(&ldac_headset());
+        let preference =
+            |codecs: &[&str]| -> Vec<String> { codecs.iter().map(|c| c.to_string()).collect() };
+
+        assert_eq!(
+            preferred_codec(&available, &preference(&["aac", "ldac"])).as_deref(),
+            Some("aac")
+        );
+        // Codecs the device lacks are skipped
+        assert_eq!(
+            preferred_codec(&available, &preference(&["aptx_hd", "sbc"])).as_deref(),
+            Some("sbc")
+        );
+        // None of them on offer, back to the ranking
+        assert_eq!(
+            preferred_codec(&available, &preference(&["aptx"])).as_deref(),
+            Some("ldac")
+        );
+    }
+
+    #[test]
+    fn pulseaudio_card_without_profiles_is_pending() {
+        let card = pa_card(
+            r#"{
+                "name": "bluez_card.AA_BB_CC_DD_EE_FF",
+                "properties": {"device.description": "WH-1000XM4"},
+                "profiles": [],
+                "active_profile": null
+            }"#,
+        );
+
+        let device = pulseaudio_device(&card, &[]).unwrap();
+        assert!(device.pending);
+        assert!(device.profiles.is_empty());
+        assert_eq!(device.address, addr());
+        assert_eq!(device.name.as_deref(), Some("WH-1000XM4"));
+        assert_eq!(device.id, AudioDeviceId::Pulseaudio(card.name));
+    }
+
+    #[test]
+    fn active_profile_is_looked_up_by_index() {
+        let mut device = ldac_headset();
+
+        device.active_profile_index = Some(3);
+        assert_eq!(device.active_profile_name(), Some("a2dp-sink-ldac"));
+
+        // Index 0 is "off", which is not listed
+        device.active_profile_index = Some(0);
+        assert!(device.active_profile().is_none());
+
+        device.active_profile_index = Some(42);
+        assert!(device.active_profile().is_none());
+
+        device.active_profile_index = None;
+        assert_eq!(device.active_profile_name(), None);
+    }
+
+    #[test]
+    fn mock_backend_answers_without_running_anything() {
+        let _backend = mock_backend(MOCK_DEVICES);
+        let device = get_audio_device(&addr()).unwrap();
+
+        assert_eq!(active_stream_count(&device), 0);
+        assert_eq!(hw_volume_support(&addr()), HwVolumeSupport::default());
+        assert_eq!(sco_active(&addr()), None);
+        assert!(supported_sample_rates(&addr()).is_empty());
+        assert!(set_absolute_volume(&device.id, true).is_ok());
+        assert!(apply_eq_preset(&device.id, "vocal").is_ok());
+        assert!(matches!(
+            apply_eq_preset(&device.id, "loudness"),
+            Err(ProfileError::UnknownPreset(_))
+        ));
+    }
+
+    #[test]
+    fn saved_preference_overrides_connect_priority() {
+        let advertised = [ProfileKind::A2dp, ProfileKind::HspHfp];
+
+        assert_eq!(
+            predict_connect_profile(&advertised, None),
+            Some(ProfileKind::A2dp)
+        );
+        assert_eq!(
+            predict_connect_profile(&advertised, Some(ProfileKind::HspHfp)),
+            Some(ProfileKind::HspHfp)
+        );
+        // A preference the device does not advertise is ignored
+        assert_eq!(
+            predict_connect_profile(&advertised, Some(ProfileKind::AudioGateway)),
+            Some(ProfileKind::A2dp)
+        );
+        assert_eq!(predict_connect_profile(&[], Some(ProfileKind::A2dp)), None);
+    }
+
+    #[test]
+    fn eq_module_args_dry_run() {
+        let sink = "bluez_output.AA_BB_CC_DD_EE_FF.1";
+        assert_eq!(
+            eq_module_args(sink, "bass-boost").unwrap(),
+            [
+                "sink_name=bluetui_eq_bass-boost",
+                "sink_master=bluez_output.AA_BB_CC_DD_EE_FF.1",
+                "plugin=mbeq_1197",
+                "label=mbeq",
+                "control=6,6,5,4,2,0,0,0,0,0,0,0,0,0,0",
+            ]
+        );
+        assert_eq!(eq_module_args(sink, "loudness"), None);
+
+        // Every preset covers all 15 bands
+        for preset in eq_presets() {
+            let args = eq_module_args(sink, preset).unwrap();
+            let control = args.last().unwrap().strip_prefix("control=").unwrap();
+            assert_eq!(control.split(',').count(), 15, "{preset}");
+        }
+    }
+
+    #[test]
+    fn hw_volume_rule_matches_the_device() {
+        let rule = hw_volume_rule("bluez_card.AA_BB_CC_DD_EE_FF");
+        assert!(rule.contains(r#"matches = [ { device.name = "bluez_card.AA_BB_CC_DD_EE_FF" } ]"#));
+        assert!(rule.contains("actions = { update-props = { bluez5.hw-volume = [ ] } }"));
+        assert!(rule.starts_with("# Written by bluetui"));
+
+        // Our own rule must not read as a codec restriction
+        assert_eq!(parse_codecs_setting(&rule), None);
+    }
+
+    #[test]
+    fn absolute_volume_is_refused_on_pulseaudio() {
+        let _backend = BACKEND.lock().unwrap_or_else(|e| e.into_inner());
+        let card = AudioDeviceId::Pulseaudio("bluez_card.AA_BB_CC_DD_EE_FF".into());
+        let refused = set_absolute_volume(&card, false);
+        assert_eq!(
+            refused,
+            Err(ProfileError::RestartRequired("absolute volume"))
+        );
+        assert!(
+            refused
+                .unwrap_err()
+                .hint()
+                .unwrap()
+                .contains("module-bluez5-discover")
+        );
+    }
+
+    #[test]
+    fn absolute_volume_message_says_what_to_restart() {
+        let change = AbsoluteVolumeChange {
+            enabled: false,
+            requires_wireplumber_restart: true,
+            requires_reconnect: true,
+        };
+        assert_eq!(
+            change.to_string(),
+            "Absolute volume off, restart WirePlumber (systemctl --user restart wireplumber) \
+             and reconnect the device to apply"
+        );
+
+        let change = AbsoluteVolumeChange {
+            enabled: true,
+            requires_wireplumber_restart: false,
+            ..change
+        };
+        assert_eq!(
+            change.to_string(),
+            "Absolute volume on, reconnect the device to apply"
+        );
+    }
+
+    #[test]
+    fn sample_rates_read_every_spa_shape() {
+        assert_eq!(sample_rates(&serde_json::json!(48000)), [48000]);
+        assert_eq!(
+            sample_rates(&serde_json::json!([44100, 48000])),
+            [44100, 48000]
+        );
+        let mut rates = sample_rates(&serde_json::json!({
+            "default": 48000, "alt1": 44100, "alt2": 96000, "min": 8000, "max": 192000
+        }));
+        rates.sort_unstable();
+        assert_eq!(rates, [44100, 48000, 96000]);
+        assert!(sample_rates(&serde_json::json!("48000")).is_empty());
+    }
+
+    #[test]
+    fn pipewire_sample_rates_list_the_sink_rates() {
+        let entries = dump(SAMPLE_DUMP);
+        let node = pipewire_sink_node(&entries, 40).unwrap();
+        // EnumFormat offers 44.1k/48k/96k and Format repeats 96k, kept once
+        assert_eq!(pipewire_sample_rates(node), [44100, 48000, 96000]);
+
+        let bare = dump(&SAMPLE_DUMP.replace(r#""params""#, r#""unused""#));
+        assert!(pipewire_sample_rates(pipewire_sink_node(&bare, 40).unwrap()).is_empty());
+    }
+
+    #[test]
+    fn grouped_profiles_split_a2dp_from_headset() {
+        let mut device = ldac_headset();
+        device
+            .profiles
+            .push(profile(0, "off", ProfileCategory::Off));
+        device.active_profile_index = Some(3);
+
+        let groups = grouped_profiles(&device);
+        let names =
+            |category| -> Vec<&str> { groups[&category].iter().map(|p| p.name.as_str()).collect() };
+        assert_eq!(
+            groups.keys().copied().collect::<Vec<_>>(),
+            [
+                ProfileCategory::Output,
+                ProfileCategory::Duplex,
+                ProfileCategory::Off
+            ]
+        );
+        assert_eq!(
+            names(ProfileCategory::Output),
+            ["a2dp-sink-sbc", "a2dp-sink-aac", "a2dp-sink-ldac"]
+        );
+        assert_eq!(names(ProfileCategory::Duplex), ["headset-head-unit"]);
+        assert_eq!(groups.values().map(Vec::len).sum::<usize>(), 5);
+
+        let active = groups[&ProfileCategory::Output]
+            .iter()
+            .find(|p| Some(p.index) == device.active_profile_index);
+        assert_eq!(active.map(|p| p.name.as_str()), Some("a2dp-sink-ldac"));
+
+        // Categories read from a real dump land in the same groups
+        let parsed = parse_pipewire_device(&dump(SAMPLE_DUMP), &addr()).unwrap();
+        let groups = grouped_profiles(&parsed);
+        assert_eq!(groups[&ProfileCategory::Output][0].name, "a2dp-sink");
+        assert_eq!(
+            groups[&ProfileCategory::Duplex][0].name,
+            "headset-head-unit"
+        );
+        // "off" is left out of the parsed profiles
+        assert!(!groups.contains_key(&ProfileCategory::Off));
+    }
+
+    #[test]
+    fn duplicate_device_objects_keep_the_active_one() {
+        let _backend = BACKEND.lock().unwrap_or_else(|e| e.into_inner());
+        let log = temp_path("duplicates.jsonl");
+        let _ = std::fs::remove_file(&log);
+        enable_event_log(&log).unwrap();
+
+        // The stale object lost its active profile, the new one after it has it
+        let mut objects: serde_json::Value = serde_json::from_str(SAMPLE_DUMP).unwrap();
+        let mut fresh = objects[0].clone();
+        fresh["id"] = 50.into();
+        objects[0]["info"]["params"]["Profile"] = serde_json::json!([]);
+        objects.as_array_mut().unwrap().push(fresh);
+        let entries = dump(&objects.to_string());
+
+        let device = parse_pipewire_device(&entries, &addr()).unwrap();
+        assert_eq!(device.id, AudioDeviceId::Pipewire(50));
+        assert_eq!(device.active_profile_index, Some(1));
+        disable_event_log();
+
+        let mut text = String::new();
+        for _ in 0..100 {
+            text = std::fs::read_to_string(&log).unwrap_or_default();
+            if text.ends_with('\n') {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        let _ = std::fs::remove_file(&log);
+
+        let event: serde_json::Value = serde_json::from_str(text.trim()).unwrap();
+        assert_eq!(event["kind"], "duplicate");
+        assert_eq!(event["address"], ADDR);
+        assert_eq!(event["old"], "40");
+        assert_eq!(event["new"], "50");
+    }
+
+    #[test]
+    fn bitrate_comes_from_the_sink_node() {
+        let entries = dump(SAMPLE_DUMP);
+        assert_eq!(pipewire_bitrate_kbps(&entries, &addr()), Some(990));
+
+        // SBC links usually report nothing
+        let silent = dump(&SAMPLE_DUMP.replace(r#""api.bluez5.bitrate": 990000,"#, ""));
+        assert_eq!(pipewire_bitrate_kbps(&silent, &addr()), None);
+
+        assert_eq!(bitrate_kbps(&serde_json::json!(328)), Some(328));
+        assert_eq!(bitrate_kbps(&serde_json::json!("660 kbps")), Some(660));
+        assert_eq!(bitrate_kbps(&serde_json::json!("990000bps")), Some(990));
+        assert_eq!(bitrate_kbps(&serde_json::json!(0)), None);
+        assert_eq!(bitrate_kbps(&serde_json::Value::Null), None);
+    }
+
+    fn elements(json: &str) -> Vec<String> {
+        JsonArrayElements::new(json.as_bytes())
+            .map(|e| String::from_utf8(e).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn json_array_elements_split_only_at_the_top_level() {
+        assert_eq!(
+            elements(r#"[{"a": "] } ,"}, {"b": "say \"hi\", ]"}]"#),
+            [r#"{"a": "] } ,"}"#, r#"{"b": "say \"hi\", ]"}"#]
+        );
+        assert_eq!(
+            elements(r#" [ {"list": [{"x": [1, 2]}, {}], "y": {"z": []}} , 3, "s\\" ] "#),
+            [
+                r#"{"list": [{"x": [1, 2]}, {}], "y": {"z": []}}"#,
+                "3",
+                r#""s\\""#
+            ]
+        );
+        assert!(elements("[]").is_empty());
+        assert!(elements(" [ \n ] ").is_empty());
+        assert!(elements("").is_empty());
+    }
+
+    #[test]
+    fn single_lookup_stops_reading_once_the_device_is_in() {
+        // The device and its sink up front, then a big graph of unrelated clients
+        let mut objects: Vec<serde_json::Value> = serde_json::from_str(SAMPLE_DUMP).unwrap();
+        objects.extend((100..2100).map(|id| {
+            serde_json::json!({"id": id, "type": "PipeWire:Interface:Client", "info": {"props": {}}})
+        }));
+        let json = serde_json::to_string(&objects).unwrap();
+
+        let mut input = json.as_bytes();
+        let (entries, early) = read_entries_until(&mut input, |entries| {
+            pipewire_lookup_complete(entries, &addr())
+        });
+        assert!(early);
+        assert_eq!(entries.len(), 2);
+        assert!(
+            input.len() > json.len() * 9 / 10,
+            "read {} bytes",
+            json.len() - input.len()
+        );
+        assert!(parse_pipewire_device(&entries, &addr()).is_some());
+
+        let mut input = json.as_bytes();
+        let (entries, early) = read_entries_until(&mut input, |_| false);
+        assert!(!early);
+        assert_eq!(entries.len(), objects.len());
+        assert!(input.is_empty());
+    }
+
+    #[test]
+    fn locked_device_refuses_every_unforced_switch() {
+        let _backend = mock_backend(MOCK_DEVICES);
+        let device = get_audio_device(&addr()).unwrap();
+        let aac = device.profiles.iter().find(|p| p.index == 2).unwrap();
+        let snapshot = snapshot_all();
+        set_locked(&addr(), true).unwrap();
+        assert!(is_locked(&addr()));
+
+        let locked = Err(ProfileError::Locked(addr()));
+        assert_eq!(switch_device_profile(&device, aac, false), locked);
+        assert_eq!(switch_codec(&device, "aac", false), locked);
+        assert_eq!(
+            switch_preferred_codec(&device, &["aac".into()]),
+            locked.clone().map(|_| None)
+        );
+        assert_eq!(reapply_active_profile(&device), locked);
+        assert_eq!(
+            get_audio_device(&addr()).unwrap().active_profile_index,
+            Some(1)
+        );
+
+        // Forcing goes through the lock, restoring does not
+        switch_device_profile(&device, aac, true).unwrap();
+        assert_eq!(
+            get_audio_device(&addr()).unwrap().codec.as_deref(),
+            Some("aac")
+        );
+        let restored = restore(&snapshot);
+        assert_eq!(restored, [(addr(), Err(ProfileError::Locked(addr())))]);
+
+        let device = get_audio_device(&addr()).unwrap();
+        switch_codec(&device, "ldac", true).unwrap();
+        assert_eq!(
+            get_audio_device(&addr()).unwrap().active_profile_index,
+            Some(1)
+        );
+
+        set_locked(&addr(), false).unwrap();
+        assert!(!is_locked(&addr()));
+        switch_device_profile(&device, aac, false).unwrap();
+    }
+
+    #[test]
+    fn suspended_node_reads_as_idle() {
+        let suspended =
+            dump(&SAMPLE_DUMP.replace(r#""state": "running""#, r#""state": "suspended""#));
+        assert_eq!(pipewire_device_suspended(&suspended, 40), Some(true));
+        assert_eq!(
+            pipewire_device_suspended(&dump(SAMPLE_DUMP), 40),
+            Some(false)
+        );
+
+        // A second node still running keeps the device awake
+        let mut objects: serde_json::Value = serde_json::from_str(
+            &SAMPLE_DUMP.replace(r#""state": "running""#, r#""state": "suspended""#),
+        )
+        .unwrap();
+        let mut source = objects[1].clone();
+        source["id"] = 43.into();
+        source["info"]["state"] = "idle".into();
+        objects.as_array_mut().unwrap().push(source);
+        assert_eq!(
+            pipewire_device_suspended(&dump(&objects.to_string()), 40),
+            Some(false)
+        );
+
+        let stateless = dump(&SAMPLE_DUMP.replace(r#""state": "running","#, ""));
+        assert_eq!(pipewire_device_suspended(&stateless, 40), None);
+        assert_eq!(pipewire_device_suspended(&dump(SAMPLE_DUMP), 99), None);
+    }
+
+    fn bluer_error(kind: bluer::ErrorKind, message: &str) -> ProfileError {
+        bluer::Error {
+            kind,
+            message: message.into(),
+        }
+        .into()
+    }
+
+    #[test]
+    fn each_error_gets_its_own_hint() {
+        use bluer::{ErrorKind, InternalErrorKind};
+
+        let cases = [
+            (ProfileError::BackendNotInstalled("wpctl"), Some("Install pipewire-utils")),
+            (
+                ProfileError::Io {
+                    command: "wpctl",
+                    message: "denied".into(),
+                },
+                None,
+            ),
+            (
+                ProfileError::CommandFailed {
+                    command: "wpctl",
+                    stderr: "boom".into(),
+                },
+                None,
+            ),
+            (ProfileError::DeviceNotFound(40), Some("may have disconnected")),
+            (
+                ProfileError::NotADevice {
+                    id: 41,
+                    media_class: "Audio/Sink".into(),
+                },
+                None,
+            ),
+            (ProfileError::NoAudioDevice(addr()), Some("Connect the device")),
+            (
+                ProfileError::Dbus("org.freedesktop.DBus.Error.ServiceUnknown: The name org.bluez was not provided by any .service files".into()),
+                Some("Start bluetooth.service"),
+            ),
+            (ProfileError::Dbus("Timeout was reached".into()), None),
+            (
+                bluer_error(ErrorKind::Internal(InternalErrorKind::DBusConnectionLost), ""),
+                Some("Start bluetooth.service"),
+            ),
+            (bluer_error(ErrorKind::NotReady, "Resource Not Ready"), Some("Power the adapter on")),
+            (bluer_error(ErrorKind::Failed, "Page Timeout"), Some("bring it in range")),
+            (
+                bluer_error(ErrorKind::Failed, "br-connection-page-timeout"),
+                Some("bring it in range"),
+            ),
+            (
+                bluer_error(ErrorKind::ConnectionAttemptFailed, "Host is down"),
+                Some("bring it in range"),
+            ),
+            (
+                bluer_error(ErrorKind::AuthenticationFailed, "Authentication Failed"),
+                Some("pair it again"),
+            ),
+            (bluer_error(ErrorKind::DoesNotExist, ""), Some("scan for it again")),
+            (bluer_error(ErrorKind::Failed, "Operation failed"), None),
+            (bluer_error(ErrorKind::InProgress, ""), None),
+            (ProfileError::CodecUnavailable("ldac".into()), Some("Pick one of the codecs")),
+            (ProfileError::ProfileUnavailable("a2dp-sink".into()), Some("Reconnect the device")),
+            (ProfileError::UnknownPreset("loud".into()), Some("built-in presets")),
+            (ProfileError::Locked(addr()), Some("Unlock it with `l`")),
+            (ProfileError::NoAdapter, Some("plug the adapter in")),
+            (
+                ProfileError::RadioBlocked(RfkillState::SoftBlocked),
+                Some("sudo rfkill unblock bluetooth"),
+            ),
+            (
+                ProfileError::RadioBlocked(RfkillState::HardBlocked),
+                Some("hardware switch"),
+            ),
+            (
+                ProfileError::LeftOff {
+                    profile: "a2dp-sink".into(),
+                    reason: "boom".into(),
+                },
+                Some("Pick a profile"),
+            ),
+            (
+                ProfileError::RestartRequired("absolute volume"),
+                Some("`pulseaudio -k`"),
+            ),
+        ];
+
+        for (error, expected) in cases {
+            let hint = error.hint();
+            match expected {
+                Some(expected) => assert!(
+                    hint.is_some_and(|h| h.contains(expected)),
+                    "{error:?}: {hint:?}"
+                ),
+                None => assert_eq!(hint, None, "{error:?}"),
+            }
+            // The hint adds to the message, never repeats it
+            if let Some(hint) = hint {
+                let message = error.to_string().to_lowercase();
+                assert!(!hint.to_lowercase().contains(&message), "{error:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn codec_setting_ignores_per_device_rules() {
+        let config = r#"
+# Keep the link stable, no LDAC
+monitor.bluez.properties = {
+  bluez5.codecs = [ sbc sbc_xq aac ]   # what we negotiate
+}
+
+monitor.bluez.rules = [
+  {
+    matches = [ { device.name = "~bluez_card.*" } ]
+    actions = {
+      update-props = { bluez5.codecs = [ ldac ], device.description = "[x]" }
+    }
+  }
+]
+"#;
+        let enabled = parse_codecs_setting(config).unwrap();
+        assert_eq!(enabled, ["sbc", "sbc_xq", "aac"]);
+
+        let states = codec_states(Some(&enabled));
+        let state = |codec: &str| states.iter().find(|(c, _)| c == codec).map(|(_, on)| *on);
+        assert_eq!(state("aac"), Some(true));
+        assert_eq!(state("ldac"), Some(false));
+        assert_eq!(state("msbc"), None);
+        assert!(codec_states(None).iter().all(|(_, on)| *on));
+
+        let lua = r#"
+bluez_monitor.properties = {
+  ["bluez5.codecs"] = "[ sbc aac ldac ]",
+}
+bluez_monitor.rules = {
+  { matches = {{{ "device.name", "matches", "bluez_card.*" }}},
+    apply_properties = { ["bluez5.codecs"] = "[ sbc ]" } },
+}
+"#;
+        assert_eq!(parse_codecs_setting(lua).unwrap(), ["sbc", "aac", "ldac"]);
+
+        // Only a per-device list: nothing global
+        let rules_only = &config[config.find("monitor.bluez.rules").unwrap()..];
+        assert_eq!(parse_codecs_setting(rules_only), None);
+    }
+
+    #[test]
+    fn bounded_refresh_returns_what_answered_in_time() {
+        let fast = addr();
+        let missing = Address::new([0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0x01]);
+        let slow = Address::new([0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0x02]);
+
+        let started = Instant::now();
+        let report =
+            refresh_bounded_with(&[slow, fast, missing], Duration::from_millis(200), |addr| {
+                match addr.0[5] {
+                    0xFF => Some(ldac_headset()),
+                    0x02 => {
+                        thread::sleep(Duration::from_secs(2));
+                        Some(ldac_headset())
+                    }
+                    _ => None,
+                }
+            });
+
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert_eq!(report.devices.len(), 1);
+        assert_eq!(report.devices[0].address, fast);
+        assert_eq!(report.missing, [missing]);
+        assert_eq!(report.stale, [slow]);
+    }
+
+    #[test]
+    fn explain_match_names_the_object_and_property() {
+        let objects: Vec<serde_json::Value> = serde_json::from_str(SAMPLE_DUMP).unwrap();
+        let pointers = &["/info/props/api.bluez5.address"];
+        let matches = matching_objects(&objects, pointers, &addr());
+        assert_eq!(
+            matches
+                .iter()
+                .map(|(o, _, _)| o["id"].as_u64())
+                .collect::<Vec<_>>(),
+            [Some(40), Some(41)]
+        );
+        let text = explain_matches("pw-dump", &matches);
+        assert!(
+            text.starts_with(
+                "pw-dump object 40 (PipeWire:Interface:Device): api.bluez5.address = "
+            ),
+            "{text}"
+        );
+        assert_eq!(text.lines().count(), 2);
+
+        let cards: Vec<serde_json::Value> = serde_json::from_str(
+            r#"[{"name": "bluez_card.AA_BB_CC_DD_EE_FF", "properties": {"device.string": "AA:BB:CC:DD:EE:FF"}},
+                {"name": "alsa_card.pci", "properties": {"device.string": "0"}}]"#,
+        )
+        .unwrap();
+        let pointers = &[
+            "/properties/api.bluez5.address",
+            "/properties/device.string",
+        ];
+        assert_eq!(
+            explain_matches("pactl", &matching_objects(&cards, pointers, &addr())),
+            "pactl card bluez_card.AA_BB_CC_DD_EE_FF: device.string = AA:BB:CC:DD:EE:FF"
+        );
+        assert_eq!(
+            explain_matches("pactl", &[]),
+            "no pactl object has this address"
+        );
+    }
+
+    #[test]
+    fn mock_devices_are_never_suspended() {
+        let _backend = mock_backend(MOCK_DEVICES);
+        assert_eq!(is_node_suspended(&addr()), None);
+    }
+
+    #[test]
+    fn mock_bitrate_comes_from_the_dataset() {
+        let _backend = mock_backend(MOCK_DEVICES);
+        assert_eq!(active_bitrate_kbps(&addr()), None);
+        assert_eq!(audio_quality_summary(&addr()).as_deref(), Some("LDAC"));
+        drop(_backend);
+
+        let _backend = mock_backend(
+            &MOCK_DEVICES.replace(r#""volume": 60"#, r#""volume": 60, "bitrate": 660"#),
+        );
+        assert_eq!(active_bitrate_kbps(&addr()), Some(660));
+        assert_eq!(
+            audio_quality_summary(&addr()).as_deref(),
+            Some("LDAC 660kbps")
+        );
     }
 }
@@ -0,0 +1,253 @@
+use std::{collections::HashMap, time::Duration};
+
+use bluer::Address;
+use dbus::{
+    Path,
+    arg::{PropMap, prop_cast},
+    blocking::{
+        Connection,
+        stdintf::org_freedesktop_dbus::{ObjectManager, Properties},
+    },
+};
+
+use super::{ProfileError, run};
+
+const DBUS_TIMEOUT: Duration = Duration::from_secs(2);
+const MEDIA_TRANSPORT: &str = "org.bluez.MediaTransport1";
+const MEDIA_PLAYER: &str = "org.bluez.MediaPlayer1";
+const DEVICE: &str = "org.bluez.Device1";
+const BATTERY: &str = "org.bluez.Battery1";
+
+/// AVRCP absolute volume goes from 0 to 127.
+const TRANSPORT_VOLUME_MAX: u16 = 127;
+
+#[derive(Debug, Default)]
+pub struct Info {
+    pub name: Option<String>,
+    pub battery: Option<u8>,
+    /// Service UUIDs the device advertises, lowercase
+    pub uuids: Vec<String>,
+    pub connected: bool,
+}
+
+pub fn info(addr: &Address) -> Option<Info> {
+    let output = run("bluetoothctl", &["info", &addr.to_string()]).ok()?;
+    if !output.status.success() {
+        return None;
+    }
+
+    Some(parse_info(&String::from_utf8_lossy(&output.stdout)))
+}
+
+fn parse_info(text: &str) -> Info {
+    let mut info = Info::default();
+
+    for line in text.lines() {
+        let Some((key, value)) = line.trim().split_once(": ") else {
+            continue;
+        };
+        match key {
+            "Alias" => info.name = Some(value.to_string()),
+            "Connected" => info.connected = value == "yes",
+            "Name" if info.name.is_none() => info.name = Some(value.to_string()),
+            // "Battery Percentage: 0x52 (82)"
+            "Battery Percentage" => {
+                info.battery = value
+                    .split_once('(')
+                    .and_then(|(_, pct)| pct.trim_end_matches(')').parse().ok());
+            }
+            // "UUID: Audio Sink                (0000110b-0000-1000-8000-00805f9b34fb)"
+            "UUID" => {
+                if let Some((_, uuid)) = value.rsplit_once('(') {
+                    info.uuids
+                        .push(uuid.trim_end_matches(')').to_ascii_lowercase());
+                }
+            }
+            _ => {}
+        }
+    }
+
+    info
+}
+
+/// Whether the default adapter is powered, from `bluetoothctl show`.
+pub fn adapter_powered() -> Option<bool> {
+    let output = run("bluetoothctl", &["show"]).ok()?;
+    if !output.status.success() {
+        return None;
+    }
+
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("Powered: "))
+        .map(|value| value == "yes")
+}
+
+/// Media transport of the device, present while audio is connected.
+pub struct Transport {
+    pub path: Path<'static>,
+    /// `None` when the device does not support absolute volume
+    pub volume: Option<u16>,
+}
+
+type ManagedObjects = HashMap<Path<'static>, HashMap<String, PropMap>>;
+
+fn managed_objects() -> Option<ManagedObjects> {
+    let conn = Connection::new_system().ok()?;
+    conn.with_proxy("org.bluez", "/", DBUS_TIMEOUT)
+        .get_managed_objects()
+        .ok()
+}
+
+/// Find the object implementing `interface` below the device object,
+/// e.g. /org/bluez/hci0/dev_XX_XX_XX_XX_XX_XX/sep1/fd0 for a transport.
+fn find_device_object<'a>(
+    objects: &'a ManagedObjects,
+    addr: &Address,
+    interface: &str,
+) -> Option<(&'a Path<'static>, &'a PropMap)> {
+    let device_segment = format!("/dev_{}/", addr.to_string().replace(':', "_"));
+
+    objects.iter().find_map(|(path, interfaces)| {
+        let props = interfaces.get(interface)?;
+        path.contains(&device_segment).then_some((path, props))
+    })
+}
+
+/// Alias and battery level of the device object itself, read over D-Bus.
+pub fn alias_and_battery(addr: &Address) -> Option<(Option<String>, Option<u8>)> {
+    let objects = managed_objects()?;
+    let device_suffix = format!("/dev_{}", addr.to_string().replace(':', "_"));
+    let interfaces = objects
+        .iter()
+        .find_map(|(path, interfaces)| path.ends_with(&device_suffix).then_some(interfaces))?;
+
+    let alias = interfaces
+        .get(DEVICE)
+        .and_then(|props| prop_cast::<String>(props, "Alias"))
+        .cloned();
+    let battery = interfaces
+        .get(BATTERY)
+        .and_then(|props| prop_cast::<u8>(props, "Percentage"))
+        .copied();
+    Some((alias, battery))
+}
+
+/// Look the transport up through the BlueZ object manager.
+pub fn transport(addr: &Address) -> Option<Transport> {
+    let objects = managed_objects()?;
+    let (path, props) = find_device_object(&objects, addr, MEDIA_TRANSPORT)?;
+
+    Some(Transport {
+        path: path.clone(),
+        volume: prop_cast::<u16>(props, "Volume").copied(),
+    })
+}
+
+/// Set the AVRCP absolute volume, `percent` is mapped onto 0..=127.
+pub fn set_transport_volume(path: &Path<'static>, percent: u8) -> Result<(), ProfileError> {
+    let conn = Connection::new_system().map_err(|e| ProfileError::Dbus(e.to_string()))?;
+    let volume = (u16::from(percent.min(100)) * TRANSPORT_VOLUME_MAX).div_ceil(100);
+
+    conn.with_proxy("org.bluez", path, DBUS_TIMEOUT)
+        .set(MEDIA_TRANSPORT, "Volume", volume)
+        .map_err(|e| ProfileError::Dbus(e.to_string()))
+}
+
+pub fn transport_volume_percent(volume: u16) -> u8 {
+    ((u32::from(volume.min(TRANSPORT_VOLUME_MAX)) * 100) / u32::from(TRANSPORT_VOLUME_MAX)) as u8
+}
+
+/// AVRCP player the device registered, present while it offers media controls.
+pub struct MediaPlayer {
+    pub path: Path<'static>,
+    /// `None` when the device sends no metadata, some only offer the buttons
+    pub track: Option<Track>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Track {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+}
+
+pub fn media_player(addr: &Address) -> Option<MediaPlayer> {
+    let objects = managed_objects()?;
+    let (path, props) = find_device_object(&objects, addr, MEDIA_PLAYER)?;
+
+    Some(MediaPlayer {
+        path: path.clone(),
+        track: parse_track(props),
+    })
+}
+
+/// The player's `Track` property, `None` when it is missing or carries no title,
+/// artist or album.
+fn parse_track(props: &PropMap) -> Option<Track> {
+    let track = prop_cast::<PropMap>(props, "Track")?;
+    let field = |key| {
+        prop_cast::<String>(track, key)
+            .filter(|value| !value.is_empty())
+            .cloned()
+    };
+    let track = Track {
+        title: field("Title"),
+        artist: field("Artist"),
+        album: field("Album"),
+    };
+    (track != Track::default()).then_some(track)
+}
+
+/// Call one of the player's argument-less methods, `Play`, `Pause`, `Next`...
+pub fn media_player_command(path: &Path<'static>, method: &str) -> Result<(), ProfileError> {
+    let conn = Connection::new_system().map_err(|e| ProfileError::Dbus(e.to_string()))?;
+
+    conn.with_proxy("org.bluez", path, DBUS_TIMEOUT)
+        .method_call(MEDIA_PLAYER, method, ())
+        .map_err(|e| ProfileError::Dbus(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dbus::arg::{RefArg, Variant};
+
+    fn props<const N: usize>(entries: [(&str, Box<dyn RefArg>); N]) -> PropMap {
+        entries
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), Variant(value)))
+            .collect()
+    }
+
+    #[test]
+    fn player_without_metadata_has_no_track() {
+        // Controls only: no Track at all, or one with nothing filled in
+        assert_eq!(
+            parse_track(&props([("Status", Box::new("paused".to_string()))])),
+            None
+        );
+        assert_eq!(
+            parse_track(&props([("Track", Box::new(PropMap::new()))])),
+            None
+        );
+        let blank = props([
+            ("Title", Box::new(String::new())),
+            ("Duration", Box::new(0u32)),
+        ]);
+        assert_eq!(parse_track(&props([("Track", Box::new(blank))])), None);
+
+        let track = props([
+            ("Title", Box::new("Roygbiv".to_string())),
+            ("Artist", Box::new("Boards of Canada".to_string())),
+        ]);
+        assert_eq!(
+            parse_track(&props([("Track", Box::new(track))])),
+            Some(Track {
+                title: Some("Roygbiv".into()),
+                artist: Some("Boards of Canada".into()),
+                album: None,
+            })
+        );
+    }
+}
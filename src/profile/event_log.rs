@@ -0,0 +1,99 @@
+use serde::Serialize;
+use std::{
+    fs::OpenOptions,
+    io::{self, Write},
+    path::Path,
+    sync::{Mutex, mpsc},
+    thread,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+static EVENT_LOG: Mutex<Option<mpsc::Sender<String>>> = Mutex::new(None);
+
+/// A single line of the event log.
+#[derive(Debug, Serialize)]
+pub struct Event {
+    /// Milliseconds since the UNIX epoch
+    pub timestamp: u64,
+    /// `profile`, `codec`, `volume` or `duplicate`
+    pub kind: &'static str,
+    pub address: Option<String>,
+    /// Backend the change went through: `pipewire`, `pulseaudio` or `avrcp`
+    pub backend: &'static str,
+    pub old: Option<String>,
+    pub new: String,
+    pub success: bool,
+    pub error: Option<String>,
+}
+
+impl Event {
+    pub fn new(kind: &'static str, backend: &'static str, new: impl Into<String>) -> Self {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or_default();
+
+        Self {
+            timestamp,
+            kind,
+            address: None,
+            backend,
+            old: None,
+            new: new.into(),
+            success: true,
+            error: None,
+        }
+    }
+
+    pub fn address(mut self, address: impl ToString) -> Self {
+        self.address = Some(address.to_string());
+        self
+    }
+
+    pub fn old(mut self, old: Option<impl ToString>) -> Self {
+        self.old = old.map(|o| o.to_string());
+        self
+    }
+
+    pub fn outcome<T, E: ToString>(mut self, result: &Result<T, E>) -> Self {
+        if let Err(e) = result {
+            self.success = false;
+            self.error = Some(e.to_string());
+        }
+        self
+    }
+}
+
+/// Append every profile, codec and volume change to `path` as one JSON object per line.
+///
+/// Lines are handed to a background thread, so a slow or full disk never holds up
+/// the caller; write errors are dropped. Calling this again moves the log to the new
+/// path.
+pub fn enable_event_log(path: &Path) -> io::Result<()> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    let (tx, rx) = mpsc::channel::<String>();
+
+    thread::spawn(move || {
+        for line in rx {
+            let _ = writeln!(file, "{line}").and_then(|_| file.flush());
+        }
+    });
+
+    *EVENT_LOG.lock().unwrap() = Some(tx);
+    Ok(())
+}
+
+pub fn disable_event_log() {
+    *EVENT_LOG.lock().unwrap() = None;
+}
+
+pub(super) fn record(event: Event) {
+    let guard = EVENT_LOG.lock().unwrap();
+    let Some(tx) = guard.as_ref() else {
+        return;
+    };
+
+    if let Ok(line) = serde_json::to_string(&event) {
+        let _ = tx.send(line);
+    }
+}
@@ -0,0 +1,230 @@
+use bluer::Address;
+use serde::Deserialize;
+use std::{
+    collections::{HashMap, HashSet},
+    fs, io,
+    path::Path,
+    sync::Mutex,
+};
+
+use super::{
+    AudioDevice, AudioDeviceId, AudioProfile, BackendResult, ProfileCategory, ProfileKind,
+};
+
+/// Fake devices served instead of asking PipeWire or PulseAudio, `None` unless
+/// [`enable_mock_backend`] was called.
+static MOCK: Mutex<Option<MockState>> = Mutex::new(None);
+
+/// First PipeWire id handed to fake devices, well above what a real session uses.
+const MOCK_ID_BASE: u32 = 10_000;
+
+/// Index of the `off` profile every fake device has; the dataset lists the others.
+pub(super) const OFF_PROFILE_INDEX: u32 = 0;
+
+struct MockState {
+    devices: Vec<AudioDevice>,
+    default_output: Option<Address>,
+    locked: HashSet<Address>,
+    bitrates: HashMap<Address, u32>,
+}
+
+/// One device of the dataset file, a JSON array of these.
+///
+/// ```json
+/// [{"address": "AA:BB:CC:DD:EE:FF", "name": "WH-1000XM4", "codec": "ldac",
+///   "volume": 60, "bitrate": 990, "default": true, "locked": false, "active_profile": 1,
+///   "profiles": [{"index": 1, "name": "a2dp-sink-ldac",
+///                 "description": "High Fidelity Playback (A2DP Sink, codec LDAC)"}]}]
+/// ```
+#[derive(Deserialize)]
+struct MockDevice {
+    address: Address,
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    profiles: Vec<MockProfile>,
+    #[serde(default)]
+    active_profile: Option<u32>,
+    #[serde(default)]
+    codec: Option<String>,
+    #[serde(default)]
+    volume: Option<u8>,
+    /// In kbps
+    #[serde(default)]
+    bitrate: Option<u32>,
+    #[serde(default)]
+    default: bool,
+    #[serde(default)]
+    locked: bool,
+}
+
+#[derive(Deserialize)]
+struct MockProfile {
+    index: u32,
+    name: String,
+    #[serde(default)]
+    description: String,
+}
+
+/// Serve the devices described in the JSON file at `path` instead of the real audio
+/// backend, for demos and screenshots without hardware. Profile, volume and lock
+/// changes only update the fake devices.
+pub fn enable_mock_backend(path: &Path) -> io::Result<()> {
+    let data = fs::read_to_string(path)?;
+    let devices: Vec<MockDevice> =
+        serde_json::from_str(&data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+    let default_output = devices.iter().find(|d| d.default).map(|d| d.address);
+    let locked = devices
+        .iter()
+        .filter(|d| d.locked)
+        .map(|d| d.address)
+        .collect();
+    let bitrates = devices
+        .iter()
+        .filter_map(|d| Some((d.address, d.bitrate?)))
+        .collect();
+    let devices = devices
+        .into_iter()
+        .zip(MOCK_ID_BASE..)
+        .map(|(device, id)| AudioDevice {
+            id: AudioDeviceId::Pipewire(id),
+            address: device.address,
+            name: device.name,
+            profiles: device
+                .profiles
+                .into_iter()
+                .map(|p| AudioProfile {
+                    index: p.index,
+                    category: ProfileCategory::from_kind(ProfileKind::from_profile_name(&p.name)),
+                    name: p.name,
+                    description: p.description,
+                    available: true,
+                })
+                .collect(),
+            active_profile_index: device.active_profile,
+            codec: device.codec,
+            volume: device.volume,
+            format: None,
+            pending: false,
+        })
+        .collect();
+
+    *MOCK.lock().unwrap() = Some(MockState {
+        devices,
+        default_output,
+        locked,
+        bitrates,
+    });
+    Ok(())
+}
+
+pub(super) fn is_active() -> bool {
+    MOCK.lock().unwrap().is_some()
+}
+
+pub(super) fn devices() -> Option<Vec<AudioDevice>> {
+    MOCK.lock()
+        .unwrap()
+        .as_ref()
+        .map(|state| state.devices.clone())
+}
+
+pub(super) fn default_output() -> Option<Option<Address>> {
+    MOCK.lock()
+        .unwrap()
+        .as_ref()
+        .map(|state| state.default_output)
+}
+
+/// Make the fake device behind `id` switch profile, `None` when the mock is off.
+pub(super) fn switch_profile(id: &AudioDeviceId, profile_index: u32) -> Option<BackendResult> {
+    let mut mock = MOCK.lock().unwrap();
+    let state = mock.as_mut()?;
+
+    let device = state.devices.iter_mut().find(|d| d.id == *id);
+
+    let result = match device {
+        Some(device) if profile_index == OFF_PROFILE_INDEX => {
+            device.active_profile_index = None;
+            device.codec = None;
+            BackendResult {
+                code: 0,
+                stdout: String::new(),
+                stderr: String::new(),
+            }
+        }
+        Some(device) if device.profiles.iter().any(|p| p.index == profile_index) => {
+            device.active_profile_index = Some(profile_index);
+            device.codec = device.active_profile().and_then(AudioProfile::codec);
+            BackendResult {
+                code: 0,
+                stdout: String::new(),
+                stderr: String::new(),
+            }
+        }
+        _ => BackendResult {
+            code: 1,
+            stdout: String::new(),
+            stderr: format!("no such mock device or profile: {id:?} {profile_index}"),
+        },
+    };
+    Some(result)
+}
+
+/// Set the volume of the fake device, `None` when the mock is off.
+pub(super) fn set_volume(addr: &Address, percent: u8) -> Option<bool> {
+    let mut mock = MOCK.lock().unwrap();
+    let device = mock
+        .as_mut()?
+        .devices
+        .iter_mut()
+        .find(|d| d.address == *addr);
+
+    Some(match device {
+        Some(device) => {
+            device.volume = Some(percent);
+            true
+        }
+        None => false,
+    })
+}
+
+pub(super) fn set_default_output(addr: &Address) -> Option<bool> {
+    let mut mock = MOCK.lock().unwrap();
+    let state = mock.as_mut()?;
+
+    let known = state.devices.iter().any(|d| d.address == *addr);
+    if known {
+        state.default_output = Some(*addr);
+    }
+    Some(known)
+}
+
+/// Whether the fake device is locked to its profile, `None` when the mock is off.
+pub(super) fn is_locked(addr: &Address) -> Option<bool> {
+    MOCK.lock()
+        .unwrap()
+        .as_ref()
+        .map(|state| state.locked.contains(addr))
+}
+
+pub(super) fn set_locked(addr: &Address, locked: bool) -> Option<()> {
+    let mut mock = MOCK.lock().unwrap();
+    let state = mock.as_mut()?;
+
+    if locked {
+        state.locked.insert(*addr);
+    } else {
+        state.locked.remove(addr);
+    }
+    Some(())
+}
+
+/// The bitrate the dataset gives the fake device, `None` when the mock is off.
+pub(super) fn bitrate_kbps(addr: &Address) -> Option<Option<u32>> {
+    MOCK.lock()
+        .unwrap()
+        .as_ref()
+        .map(|state| state.bitrates.get(addr).copied())
+}
@@ -0,0 +1,206 @@
+use bluer::Address;
+use std::{
+    collections::HashMap,
+    io::{BufRead, BufReader},
+    process::Child,
+    sync::{Arc, Mutex, mpsc},
+    thread,
+    time::{Duration, Instant},
+};
+
+use super::{AudioDevice, Backend, ProfileError, all_audio_devices, detect_backend, spawn_piped};
+
+type Subscribers = Arc<Mutex<Vec<(Address, mpsc::Sender<AudioDevice>)>>>;
+
+/// Profile, codec and volume of a device, compared to tell real changes apart from
+/// the unrelated noise the monitors print.
+type DeviceState = (Option<u32>, Option<String>, Option<u8>, usize);
+
+/// A profile switch makes the monitors print dozens of objects in a row; wait for
+/// this much quiet before listing the devices once for the whole burst.
+const COALESCE_QUIET: Duration = Duration::from_millis(150);
+/// Upper bound on the wait, so a monitor that never goes quiet still gets through.
+const COALESCE_MAX: Duration = Duration::from_secs(1);
+
+/// One `pw-mon` (or `pactl subscribe`) process shared by every part of the UI that
+/// wants to hear about audio changes.
+///
+/// Each subscriber gets the fresh [`AudioDevice`] whenever its own device changes;
+/// a burst of monitor output costs one device listing, see [`COALESCE_QUIET`].
+/// Subscribers whose receiver was dropped are forgotten on the next change; the
+/// monitor process is killed with the watcher.
+pub struct ProfileWatcher {
+    subscribers: Subscribers,
+    child: Child,
+}
+
+impl ProfileWatcher {
+    pub fn new() -> Result<Self, ProfileError> {
+        let (program, args): (&'static str, &[&str]) = match detect_backend() {
+            Some(Backend::Pulseaudio) => ("pactl", &["subscribe"]),
+            _ => ("pw-mon", &[]),
+        };
+
+        let mut child = spawn_piped(program, args).map_err(|e| ProfileError::spawn(program, e))?;
+
+        let stdout = child.stdout.take().expect("stdout is piped");
+        let subscribers: Subscribers = Arc::default();
+        let (changes_tx, changes) = mpsc::channel::<()>();
+
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                let Ok(line) = line else { break };
+                if is_change_event(&line) && changes_tx.send(()).is_err() {
+                    break;
+                }
+            }
+        });
+
+        thread::spawn({
+            let subscribers = subscribers.clone();
+            move || {
+                let mut last = HashMap::new();
+                while changes.recv().is_ok() {
+                    let open = coalesce(&changes);
+                    if !subscribers.lock().unwrap().is_empty() {
+                        dispatch(&subscribers, &all_audio_devices(), &mut last);
+                    }
+                    if !open {
+                        break;
+                    }
+                }
+            }
+        });
+
+        Ok(Self { subscribers, child })
+    }
+
+    /// Receive the device behind `addr` every time its profile, codec or volume
+    /// changes.
+    pub fn subscribe(&self, addr: Address) -> mpsc::Receiver<AudioDevice> {
+        let (tx, rx) = mpsc::channel();
+        self.subscribers.lock().unwrap().push((addr, tx));
+        rx
+    }
+}
+
+impl Drop for ProfileWatcher {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+fn is_change_event(line: &str) -> bool {
+    let line = line.trim_start();
+    // pw-mon starts every object dump with one of these
+    line.starts_with("added:")
+        || line.starts_with("changed:")
+        || line.starts_with("removed:")
+        // pactl subscribe: "Event 'change' on card #42"
+        || (line.starts_with("Event ") && (line.contains(" card ") || line.contains(" sink ")))
+}
+
+/// Swallow the rest of a burst of changes. `false` once the monitor has exited.
+fn coalesce(changes: &mpsc::Receiver<()>) -> bool {
+    let deadline = Instant::now() + COALESCE_MAX;
+    loop {
+        let wait = COALESCE_QUIET.min(deadline.saturating_duration_since(Instant::now()));
+        match changes.recv_timeout(wait) {
+            Ok(()) if Instant::now() < deadline => continue,
+            Ok(()) | Err(mpsc::RecvTimeoutError::Timeout) => return true,
+            Err(mpsc::RecvTimeoutError::Disconnected) => return false,
+        }
+    }
+}
+
+fn dispatch(
+    subscribers: &Subscribers,
+    devices: &[AudioDevice],
+    last: &mut HashMap<Address, DeviceState>,
+) {
+    let mut subscribers = subscribers.lock().unwrap();
+
+    for device in devices {
+        let state = (
+            device.active_profile_index,
+            device.codec.clone(),
+            device.volume,
+            device.profiles.len(),
+        );
+        if last.get(&device.address) == Some(&state) {
+            continue;
+        }
+        last.insert(device.address, state);
+
+        subscribers.retain(|(addr, tx)| *addr != device.address || tx.send(device.clone()).is_ok());
+    }
+
+    // Forget devices that went away so they are reported again when they come back
+    last.retain(|addr, _| devices.iter().any(|d| d.address == *addr));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::profile::AudioDeviceId;
+    use std::str::FromStr;
+
+    fn device(addr: Address, codec: &str) -> AudioDevice {
+        AudioDevice {
+            id: AudioDeviceId::Pipewire(40),
+            address: addr,
+            name: None,
+            profiles: Vec::new(),
+            active_profile_index: Some(1),
+            codec: Some(codec.into()),
+            volume: Some(50),
+            format: None,
+            pending: false,
+        }
+    }
+
+    #[test]
+    fn subscribers_only_get_their_own_device() {
+        let headset = Address::from_str("AA:BB:CC:DD:EE:FF").unwrap();
+        let speaker = Address::from_str("11:22:33:44:55:66").unwrap();
+
+        let subscribers: Subscribers = Arc::default();
+        let (headset_tx, headset_rx) = mpsc::channel();
+        let (speaker_tx, speaker_rx) = mpsc::channel();
+        subscribers.lock().unwrap().push((headset, headset_tx));
+        subscribers.lock().unwrap().push((speaker, speaker_tx));
+
+        let mut last = HashMap::new();
+        dispatch(
+            &subscribers,
+            &[device(headset, "ldac"), device(speaker, "sbc")],
+            &mut last,
+        );
+        assert_eq!(headset_rx.try_recv().unwrap().address, headset);
+        assert_eq!(speaker_rx.try_recv().unwrap().address, speaker);
+
+        // Only the headset changed
+        dispatch(
+            &subscribers,
+            &[device(headset, "aac"), device(speaker, "sbc")],
+            &mut last,
+        );
+        assert_eq!(headset_rx.try_recv().unwrap().codec.as_deref(), Some("aac"));
+        assert!(headset_rx.try_recv().is_err());
+        assert!(speaker_rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn coalesce_swallows_a_burst() {
+        let (tx, rx) = mpsc::channel();
+        for _ in 0..50 {
+            tx.send(()).unwrap();
+        }
+        assert!(coalesce(&rx));
+        assert!(rx.try_recv().is_err());
+
+        drop(tx);
+        assert!(!coalesce(&rx));
+    }
+}
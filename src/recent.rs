@@ -0,0 +1,223 @@
+use crate::app::AppResult;
+use anyhow::Context;
+use bluer::Address;
+use clap::crate_name;
+use std::{
+    path::PathBuf,
+    str::FromStr,
+    sync::OnceLock,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// How many devices the history keeps.
+const MAX_RECENT: usize = 20;
+
+/// One line of the history.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Connection {
+    addr: Address,
+    /// When it last connected, in seconds since the UNIX epoch
+    secs: u64,
+    /// The run of bluetui it connected in, see [`session`]. Lines written before
+    /// sessions were recorded have none.
+    session: Option<u64>,
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// This run of bluetui, named after when it first touched the history.
+fn session() -> u64 {
+    static SESSION: OnceLock<u64> = OnceLock::new();
+    *SESSION.get_or_init(now_secs)
+}
+
+fn recent_path() -> AppResult<PathBuf> {
+    let data_dir = dirs::data_dir()
+        .context("unable to find data_dir")?
+        .join(crate_name!());
+
+    Ok(data_dir.join("recent.txt"))
+}
+
+/// Connection history, most recent first, one `address unix-seconds session` per
+/// line.
+fn read_history() -> Vec<Connection> {
+    let Ok(contents) = recent_path().and_then(|path| {
+        std::fs::read_to_string(path).context("unable to open recent devices file")
+    }) else {
+        return Vec::new();
+    };
+
+    parse_history(&contents)
+}
+
+fn parse_history(contents: &str) -> Vec<Connection> {
+    contents
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            Some(Connection {
+                addr: Address::from_str(fields.next()?).ok()?,
+                secs: fields.next()?.parse().ok()?,
+                session: fields.next().and_then(|s| s.parse().ok()),
+            })
+        })
+        .collect()
+}
+
+/// Remember that `addr` just connected.
+pub fn record_connection(addr: &Address) -> AppResult<()> {
+    let session = session();
+    let mut history = read_history();
+    push_connection(
+        &mut history,
+        Connection {
+            addr: *addr,
+            secs: now_secs(),
+            session: Some(session),
+        },
+    );
+
+    let file_path = recent_path()?;
+    let contents = history
+        .iter()
+        .map(|c| match c.session {
+            Some(session) => format!("{} {} {session}", c.addr, c.secs),
+            None => format!("{} {}", c.addr, c.secs),
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    if let Some(data_dir) = file_path.parent()
+        && !data_dir.exists()
+    {
+        std::fs::create_dir_all(data_dir)
+            .context("unable to create parent dir(s) to recent devices file")?;
+    }
+
+    std::fs::write(file_path, contents).context("error writing recent devices file")?;
+
+    Ok(())
+}
+
+/// Move the connection's device to the front of the history, dropping the oldest
+/// past [`MAX_RECENT`].
+fn push_connection(history: &mut Vec<Connection>, connection: Connection) {
+    history.retain(|c| c.addr != connection.addr);
+    history.insert(0, connection);
+    history.truncate(MAX_RECENT);
+}
+
+/// Devices that connected lately, most recent first, with when they last connected.
+/// Connections from before a restart are mapped back onto this session's clock.
+pub fn recent_devices(limit: usize) -> Vec<(Address, Instant)> {
+    let now = SystemTime::now();
+
+    most_recent(read_history(), limit)
+        .into_iter()
+        .map(|c| {
+            let ago = now
+                .duration_since(UNIX_EPOCH + Duration::from_secs(c.secs))
+                .unwrap_or_default();
+            let at = Instant::now().checked_sub(ago).unwrap_or_else(Instant::now);
+            (c.addr, at)
+        })
+        .collect()
+}
+
+fn most_recent(mut history: Vec<Connection>, limit: usize) -> Vec<Connection> {
+    history.sort_by_key(|c| std::cmp::Reverse(c.secs));
+    history.truncate(limit);
+    history
+}
+
+/// Devices that connected during the previous run of bluetui, most recent first.
+pub fn last_session_devices() -> Vec<Address> {
+    previous_session(read_history(), session())
+}
+
+/// The devices of the newest session in `history` other than `current`.
+fn previous_session(history: Vec<Connection>, current: u64) -> Vec<Address> {
+    let Some(last) = history
+        .iter()
+        .filter_map(|c| c.session)
+        .filter(|&s| s != current)
+        .max()
+    else {
+        return Vec::new();
+    };
+
+    most_recent(history, usize::MAX)
+        .into_iter()
+        .filter(|c| c.session == Some(last))
+        .map(|c| c.addr)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(last: u8) -> Address {
+        Address::new([0xAA, 0xBB, 0xCC, 0xDD, 0xEE, last])
+    }
+
+    fn connection(last: u8, secs: u64, session: Option<u64>) -> Connection {
+        Connection {
+            addr: addr(last),
+            secs,
+            session,
+        }
+    }
+
+    #[test]
+    fn most_recent_first_up_to_the_limit() {
+        let history = parse_history(
+            "AA:BB:CC:DD:EE:01 100\nnot a line\nAA:BB:CC:DD:EE:02 300 250\nAA:BB:CC:DD:EE:03 200\n",
+        );
+        assert_eq!(history.len(), 3);
+        assert_eq!(history[1], connection(2, 300, Some(250)));
+
+        assert_eq!(
+            most_recent(history.clone(), 2),
+            [connection(2, 300, Some(250)), connection(3, 200, None)]
+        );
+        assert_eq!(most_recent(history, usize::MAX).len(), 3);
+    }
+
+    #[test]
+    fn reconnecting_moves_a_device_to_the_front() {
+        let mut history = vec![connection(1, 300, None), connection(2, 200, None)];
+        push_connection(&mut history, connection(2, 400, Some(1)));
+        assert_eq!(
+            history,
+            [connection(2, 400, Some(1)), connection(1, 300, None)]
+        );
+
+        for i in 0..MAX_RECENT as u8 + 5 {
+            push_connection(&mut history, connection(10 + i, 500 + u64::from(i), None));
+        }
+        assert_eq!(history.len(), MAX_RECENT);
+        assert_eq!(history[0].addr, addr(10 + MAX_RECENT as u8 + 4));
+    }
+
+    #[test]
+    fn last_session_is_the_newest_but_the_current_one() {
+        let history = vec![
+            connection(1, 900, Some(800)),
+            connection(2, 600, Some(500)),
+            connection(3, 700, Some(500)),
+            connection(4, 400, Some(300)),
+            connection(5, 1000, None),
+        ];
+        assert_eq!(previous_session(history.clone(), 800), [addr(3), addr(2)]);
+        // Before this run connected anything, its predecessor is still the newest
+        assert_eq!(previous_session(history.clone(), 1100), [addr(1)]);
+        assert!(previous_session(vec![connection(5, 1000, None)], 1100).is_empty());
+    }
+}
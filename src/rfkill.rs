@@ -1,42 +1,160 @@
-use std::fs;
+use std::{fmt, fs, io, path::Path};
 
 use crate::app::AppResult;
 
+const RFKILL_DIR: &str = "/sys/class/rfkill/";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RfkillState {
+    Unblocked,
+    /// Blocked in software, `rfkill unblock bluetooth` lifts it
+    SoftBlocked,
+    /// Blocked by a hardware switch or the firmware
+    HardBlocked,
+}
+
+impl RfkillState {
+    // https://www.kernel.org/doc/Documentation/ABI/stable/sysfs-class-rfkill
+    fn from_sysfs_state(state: &str) -> Option<Self> {
+        match state.trim() {
+            "0" => Some(RfkillState::SoftBlocked),
+            "1" => Some(RfkillState::Unblocked),
+            "2" => Some(RfkillState::HardBlocked),
+            _ => None,
+        }
+    }
+
+    /// What to tell the user when bluetooth is blocked.
+    pub fn hint(&self) -> Option<&'static str> {
+        match self {
+            RfkillState::Unblocked => None,
+            RfkillState::SoftBlocked => {
+                Some("Bluetooth is soft blocked, run `sudo rfkill unblock bluetooth`")
+            }
+            RfkillState::HardBlocked => {
+                Some("Bluetooth is hard blocked, check the hardware switch or the BIOS")
+            }
+        }
+    }
+}
+
+impl fmt::Display for RfkillState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RfkillState::Unblocked => write!(f, "unblocked"),
+            RfkillState::SoftBlocked => write!(f, "soft blocked"),
+            RfkillState::HardBlocked => write!(f, "hard blocked"),
+        }
+    }
+}
+
+/// Block state of the bluetooth radios. With several radios the most restrictive
+/// state wins; no radio at all reads as `Unblocked`.
+pub fn rfkill_state() -> RfkillState {
+    rfkill_state_in(Path::new(RFKILL_DIR))
+}
+
+fn rfkill_state_in(dir: &Path) -> RfkillState {
+    bluetooth_entries(dir)
+        .iter()
+        .filter_map(|entry| fs::read_to_string(entry.join("state")).ok())
+        .filter_map(|state| RfkillState::from_sysfs_state(&state))
+        .fold(RfkillState::Unblocked, combine)
+}
+
+/// Lift the soft block on every bluetooth radio. Needs write access to sysfs, so
+/// usually root. A hard block can not be lifted from software.
+pub fn rfkill_unblock() -> io::Result<()> {
+    for entry in bluetooth_entries(Path::new(RFKILL_DIR)) {
+        fs::write(entry.join("soft"), "0")?;
+    }
+    Ok(())
+}
+
+fn bluetooth_entries(dir: &Path) -> Vec<std::path::PathBuf> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+
+    entries
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| {
+            fs::read_to_string(path.join("type")).is_ok_and(|name| name.trim() == "bluetooth")
+        })
+        .collect()
+}
+
+fn combine(a: RfkillState, b: RfkillState) -> RfkillState {
+    match (a, b) {
+        (RfkillState::HardBlocked, _) | (_, RfkillState::HardBlocked) => RfkillState::HardBlocked,
+        (RfkillState::SoftBlocked, _) | (_, RfkillState::SoftBlocked) => RfkillState::SoftBlocked,
+        _ => RfkillState::Unblocked,
+    }
+}
+
 pub fn check() -> AppResult<()> {
-    if let Ok(entries) = fs::read_dir("/sys/class/rfkill/") {
-        for entry in entries {
-            let entry = entry?;
-            let entry_path = entry.path();
-
-            if let Some(_file_name) = entry_path.file_name() {
-                let name = fs::read_to_string(entry_path.join("type"))?;
-
-                if name.trim() == "bluetooth" {
-                    let state_path = entry_path.join("state");
-                    let state = fs::read_to_string(state_path)?.trim().parse::<u8>()?;
-
-                    // https://www.kernel.org/doc/Documentation/ABI/stable/sysfs-class-rfkill
-                    match state {
-                        0 => {
-                            eprintln!(
-                                r#"
+    match rfkill_state() {
+        RfkillState::SoftBlocked => {
+            eprintln!(
+                r#"
 The bluetooth device is soft blocked
 Run the following command to unblock it
 $ sudo rfkill unblock bluetooth
                     "#
-                            );
-                            std::process::exit(1);
-                        }
-                        2 => {
-                            eprintln!("The bluetooth device is hard blocked");
-                            std::process::exit(1);
-                        }
-                        _ => {}
-                    }
-                    break;
-                }
-            }
+            );
+            std::process::exit(1);
+        }
+        RfkillState::HardBlocked => {
+            eprintln!("The bluetooth device is hard blocked");
+            std::process::exit(1);
         }
+        RfkillState::Unblocked => {}
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sysfs(name: &str, radios: &[(&str, &str)]) -> std::path::PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("bluetui-rfkill-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        for (i, (kind, state)) in radios.iter().enumerate() {
+            let radio = dir.join(format!("rfkill{i}"));
+            fs::create_dir_all(&radio).unwrap();
+            fs::write(radio.join("type"), format!("{kind}\n")).unwrap();
+            fs::write(radio.join("state"), format!("{state}\n")).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn only_bluetooth_radios_are_considered() {
+        let dir = sysfs(
+            "mixed",
+            &[("wlan", "0"), ("bluetooth", "1"), ("bluetooth", "0")],
+        );
+
+        let mut entries = bluetooth_entries(&dir);
+        entries.sort();
+        assert_eq!(entries, [dir.join("rfkill1"), dir.join("rfkill2")]);
+        assert_eq!(rfkill_state_in(&dir), RfkillState::SoftBlocked);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn hard_block_wins_and_no_radio_is_unblocked() {
+        let dir = sysfs("hard", &[("bluetooth", "0"), ("bluetooth", "2")]);
+        assert_eq!(rfkill_state_in(&dir), RfkillState::HardBlocked);
+        fs::remove_dir_all(dir).unwrap();
+
+        let dir = sysfs("none", &[("wlan", "2")]);
+        assert_eq!(rfkill_state_in(&dir), RfkillState::Unblocked);
+        fs::remove_dir_all(dir).unwrap();
+
+        assert!(bluetooth_entries(Path::new("/nonexistent/rfkill")).is_empty());
+    }
+}
@@ -37,6 +37,15 @@ impl From<anyhow::Error> for StringRef {
     }
 }
 
+impl From<crate::profile::ProfileError> for StringRef {
+    fn from(err: crate::profile::ProfileError) -> Self {
+        match err.hint() {
+            Some(hint) => StringRef::Owned(format!("{err}\n{hint}")),
+            None => StringRef::Owned(err.to_string()),
+        }
+    }
+}
+
 impl AsRef<str> for StringRef {
     fn as_ref(&self) -> &str {
         self.as_str()